@@ -0,0 +1,58 @@
+//! Times constant folding over a large tree with nothing to fold, next to a
+//! deep copy of the same tree, which is what rebuilding every node amounts
+//! to. Run with `cargo bench --bench fold`.
+
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use kaleidoscope::ast::Expr;
+use kaleidoscope::operator::Operator;
+use kaleidoscope::optimize::fold_constants;
+use kaleidoscope::span::Span;
+
+/// The depth of the balanced tree of additions the passes run over.
+const DEPTH: u32 = 16;
+
+/// The number of runs each measurement is the average of.
+const RUNS: u32 = 50;
+
+/// Builds a balanced tree of `x + y` additions, `depth` levels deep, whose
+/// leaves are variables, so that nothing in it folds.
+fn tree(depth: u32) -> Expr {
+    if depth == 0 {
+        return Expr::Variable("x".to_string(), Span::default());
+    }
+
+    Expr::Binary {
+        op:   Operator::new('+'),
+        lhs:  Box::new(tree(depth - 1)),
+        rhs:  Box::new(tree(depth - 1)),
+        span: Span::default(),
+    }
+}
+
+/// Returns the average time `f` takes on a fresh copy of `expr`, leaving out
+/// the time spent making the copy and dropping what `f` returns.
+fn time<T>(expr: &Expr, f: impl Fn(Expr) -> T) -> Duration {
+    let mut total = Duration::ZERO;
+    for _ in 0..RUNS {
+        let input = expr.clone();
+        let start = Instant::now();
+        let output = black_box(f(black_box(input)));
+        total += start.elapsed();
+        drop(output);
+    }
+    total / RUNS
+}
+
+fn main() {
+    let expr = tree(DEPTH);
+    let nodes = 2usize.pow(DEPTH + 1) - 1;
+
+    println!("{nodes} nodes, average of {RUNS} runs:");
+    println!("  no-op fold_constants: {:?}", time(&expr, fold_constants));
+    println!(
+        "  deep copy:            {:?}",
+        time(&expr, |expr| (expr.clone(), expr))
+    );
+}
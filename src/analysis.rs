@@ -0,0 +1,334 @@
+//! Semantic analysis passes over parsed programs
+
+use core::fmt;
+use std::collections::HashMap;
+
+use crate::ast::{Expr, Function, Prototype};
+use crate::error::{Error, Result};
+use crate::span::Span;
+use crate::visit::{Visitor, walk_expr};
+
+/// Rewrites every call that uses named arguments into a purely positional
+/// call, by matching the names against the parameters of the callee's
+/// prototype in `program`.
+///
+/// Fails if the callee is not declared in `program`, if a name matches no
+/// parameter, if a parameter is given more than once, or if a parameter is
+/// left without an argument. On failure, `program` may be left partially
+/// rewritten.
+pub fn resolve_named_args(program: &mut [Function]) -> Result<()> {
+    let protos = prototypes(program);
+    program
+        .iter_mut()
+        .try_for_each(|func| resolve_named_args_in(func, &protos))
+}
+
+/// Rewrites the calls in `func` that use named arguments like
+/// `resolve_named_args`, but looks the callees up in `protos` rather than in
+/// a whole program, as the REPL does with the functions declared so far.
+pub fn resolve_named_args_in(func: &mut Function, protos: &HashMap<String, Prototype>) -> Result<()> {
+    match &mut func.body {
+        Some(body) => resolve_expr(body, protos),
+        None => Ok(()),
+    }
+}
+
+/// Maps the name of each function declared in `program` to its prototype.
+/// A name declared more than once maps to its last declaration.
+fn prototypes(program: &[Function]) -> HashMap<String, Prototype> {
+    program
+        .iter()
+        .filter(|func| !func.is_anon)
+        .map(|func| (func.proto.name.clone(), func.proto.clone()))
+        .collect()
+}
+
+fn resolve_expr(expr: &mut Expr, protos: &HashMap<String, Prototype>) -> Result<()> {
+    match expr {
+        Expr::Binary { lhs, rhs, .. } => {
+            resolve_expr(lhs, protos)?;
+            resolve_expr(rhs, protos)
+        },
+        Expr::Call { name, args, .. } => {
+            for arg in args.iter_mut() {
+                resolve_expr(arg, protos)?;
+            }
+
+            if args.iter().any(|arg| matches!(arg, Expr::NamedArg { .. })) {
+                let callee = protos.get(name.as_str()).ok_or_else(|| {
+                    Error::parse(format!("Named arguments in call to unknown function `{name}`."))
+                })?;
+                *args = reorder_args(name, &callee.args, core::mem::take(args))?;
+            }
+
+            Ok(())
+        },
+        Expr::For {
+            start,
+            end,
+            step,
+            body,
+            ..
+        } => {
+            resolve_expr(start, protos)?;
+            resolve_expr(end, protos)?;
+            if let Some(step) = step {
+                resolve_expr(step, protos)?;
+            }
+            resolve_expr(body, protos)
+        },
+        Expr::If { cond, then, alt, .. } => {
+            resolve_expr(cond, protos)?;
+            resolve_expr(then, protos)?;
+            resolve_expr(alt, protos)
+        },
+        Expr::NamedArg { value, .. } => resolve_expr(value, protos),
+        Expr::Do(elems, _) | Expr::Seq(elems, _) | Expr::Tuple(elems, _) => {
+            elems.iter_mut().try_for_each(|elem| resolve_expr(elem, protos))
+        },
+        Expr::Var { bindings, body, .. } => {
+            for (_, init) in bindings {
+                if let Some(init) = init {
+                    resolve_expr(init, protos)?;
+                }
+            }
+            resolve_expr(body, protos)
+        },
+        Expr::Number(..) | Expr::Variable(..) => Ok(()),
+    }
+}
+
+/// Places each argument of a call to `name` in the slot of the parameter it
+/// binds. Positional arguments always come first, as the parser enforces.
+fn reorder_args(name: &str, callee: &[String], args: Vec<Expr>) -> Result<Vec<Expr>> {
+    let mut slots: Vec<Option<Expr>> = vec![None; callee.len()];
+
+    for (index, arg) in args.into_iter().enumerate() {
+        let (index, value) = match arg {
+            Expr::NamedArg {
+                name: param, value, ..
+            } => {
+                let index = callee.iter().position(|p| *p == param).ok_or_else(|| {
+                    Error::parse(format!("Function `{name}` has no parameter named `{param}`."))
+                })?;
+                (index, *value)
+            },
+            value => (index, value),
+        };
+
+        let slot = slots
+            .get_mut(index)
+            .ok_or_else(|| Error::parse(format!("Too many arguments in call to function `{name}`.")))?;
+
+        if slot.is_some() {
+            return Err(Error::parse(format!(
+                "Parameter `{}` of function `{name}` is given more than once.",
+                callee[index]
+            )));
+        }
+
+        *slot = Some(value);
+    }
+
+    slots
+        .into_iter()
+        .zip(callee)
+        .map(|(slot, param)| {
+            slot.ok_or_else(|| {
+                Error::parse(format!(
+                    "Missing argument for parameter `{param}` of function `{name}`."
+                ))
+            })
+        })
+        .collect()
+}
+
+/// Checks that every forward declaration in `program` (a `def` without a
+/// body) is followed by a definition with the same signature.
+pub fn check_forward_decls(program: &[Function]) -> Result<()> {
+    for (i, decl) in program.iter().enumerate() {
+        if decl.is_extern || decl.body.is_some() {
+            continue;
+        }
+
+        let name = &decl.proto.name;
+        let def = program[i + 1..]
+            .iter()
+            .find(|func| func.body.is_some() && func.proto.name == *name)
+            .ok_or_else(|| Error::parse(format!("Function `{name}` is declared but never defined.")))?;
+
+        check_forward_decl(&decl.proto, &def.proto)?;
+    }
+
+    Ok(())
+}
+
+/// Checks that the definition `def` has the signature of the forward
+/// declaration `decl` of the same function, as `check_forward_decls` does for
+/// a whole program.
+pub fn check_forward_decl(decl: &Prototype, def: &Prototype) -> Result<()> {
+    if def.same_signature(decl) {
+        Ok(())
+    } else {
+        Err(Error::parse(format!(
+            "Definition of `{}` does not match its forward declaration.",
+            def.name
+        )))
+    }
+}
+
+/// Checks that every call in `funcs` names a function declared in `funcs`,
+/// by an `extern`, a forward declaration or a definition, and passes it as
+/// many arguments as it declares. The error points at the first offending
+/// call.
+pub fn check_calls(funcs: &[Function]) -> Result<()> {
+    let protos = prototypes(funcs);
+    funcs.iter().try_for_each(|func| check_calls_in(func, &protos))
+}
+
+/// Checks the calls in `func` like `check_calls`, but against the functions
+/// declared in `protos` rather than in a whole program.
+pub fn check_calls_in(func: &Function, protos: &HashMap<String, Prototype>) -> Result<()> {
+    let mut checker = CallChecker { protos, error: None };
+
+    if let Some(body) = &func.body {
+        checker.visit_expr(body);
+    }
+
+    checker.error.map_or(Ok(()), Err)
+}
+
+/// Records the first call that doesn't match the arity of its callee.
+struct CallChecker<'a> {
+    protos: &'a HashMap<String, Prototype>,
+    error:  Option<Error>,
+}
+
+impl Visitor for CallChecker<'_> {
+    fn visit_expr(&mut self, expr: &Expr) {
+        if self.error.is_some() {
+            return;
+        }
+
+        if let Expr::Call { name, args, span } = expr {
+            let message = match self.protos.get(name).map(|proto| proto.args.len()) {
+                None => Some(format!("Call to unknown function `{name}`.")),
+                Some(arity) if arity != args.len() => Some(format!(
+                    "Function `{name}` takes {arity} arguments, but {} were given.",
+                    args.len()
+                )),
+                Some(_) => None,
+            };
+
+            if let Some(message) = message {
+                self.error = Some(Error::parse(message).with_span(*span));
+                return;
+            }
+        }
+
+        walk_expr(self, expr);
+    }
+}
+
+/// A likely mistake found by an analysis pass, which doesn't prevent the
+/// program from running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Warning {
+    pub message: String,
+    pub span:    Span,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "warning: {}", self.message) }
+}
+
+/// Warns about every `for` loop in `func` whose step isn't constant, as in
+/// `for i = 0, i < 10, i in ...`. A step computed from variables or calls is
+/// allowed, but it is usually a mistake for a constant one.
+pub fn check_for_steps(func: &Function) -> Vec<Warning> {
+    let mut checker = StepChecker::default();
+    if let Some(body) = &func.body {
+        checker.visit_expr(body);
+    }
+    checker.warnings
+}
+
+#[derive(Default)]
+struct StepChecker {
+    warnings: Vec<Warning>,
+}
+
+impl Visitor for StepChecker {
+    fn visit_for(&mut self, var: &str, start: &Expr, end: &Expr, step: Option<&Expr>, body: &Expr) {
+        if let Some(step) = step
+            && !step.is_constant()
+        {
+            self.warnings.push(Warning {
+                message: format!("The step of the loop over `{var}` is not a constant: `{step}`."),
+                span:    step.span(),
+            });
+        }
+
+        self.visit_expr(start);
+        self.visit_expr(end);
+        if let Some(step) = step {
+            self.visit_expr(step);
+        }
+        self.visit_expr(body);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::parse_items;
+
+    #[test]
+    fn resolves_named_args_in_any_order() {
+        let mut program = parse_items("def sub(a, b) a - b; sub(b = 1, a = 5)");
+        resolve_named_args(&mut program).unwrap();
+        assert_eq!(program[1].body.as_ref().unwrap().to_string(), "sub(5, 1)");
+    }
+
+    #[test]
+    fn rejects_unknown_and_duplicate_names() {
+        assert!(resolve_named_args(&mut parse_items("def sub(a, b) a - b; sub(a = 1, c = 2)")).is_err());
+        assert!(resolve_named_args(&mut parse_items("def sub(a, b) a - b; sub(a = 1, a = 2)")).is_err());
+        assert!(resolve_named_args(&mut parse_items("nope(a = 1)")).is_err());
+    }
+
+    #[test]
+    fn forward_declarations_need_a_matching_definition() {
+        assert!(check_forward_decls(&parse_items("def f(x); def f(x) x")).is_ok());
+        assert!(check_forward_decls(&parse_items("def f(x); 1")).is_err());
+        assert!(check_forward_decls(&parse_items("def f(x); def f(x, y) x")).is_err());
+    }
+
+    #[test]
+    fn accepts_calls_matching_their_declarations() {
+        let program = parse_items("extern sin(x); def f(x); def g(x) f(sin(x)); def f(x) g(x)");
+        assert!(check_calls(&program).is_ok());
+    }
+
+    #[test]
+    fn rejects_arity_mismatches_and_unknown_functions() {
+        let err = check_calls(&parse_items("def add(a, b) a + b; 1 + add(1)")).unwrap_err();
+        assert_eq!(
+            err.message(),
+            "Function `add` takes 2 arguments, but 1 were given."
+        );
+        assert_eq!(err.span(), Some(Span::new(25, 31)));
+
+        let err = check_calls(&parse_items("def f(x) nope(x)")).unwrap_err();
+        assert_eq!(err.message(), "Call to unknown function `nope`.");
+    }
+
+    #[test]
+    fn warns_about_non_constant_steps() {
+        let constant = parse_items("for i = 0, i < 10, 1 in i");
+        assert!(check_for_steps(&constant[0]).is_empty());
+
+        let variable = parse_items("for i = 0, i < 10, i in i");
+        assert_eq!(check_for_steps(&variable[0]).len(), 1);
+    }
+}
@@ -0,0 +1,238 @@
+//! Hash-consed storage for expressions
+
+use std::collections::HashMap;
+
+use crate::ast::Expr;
+use crate::operator::Operator;
+use crate::span::Span;
+
+/// A handle to a node interned in an `ExprArena`. Two ids from the same arena
+/// are equal if and only if they refer to structurally identical expressions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct ExprId(usize);
+
+/// An expression node whose children are interned in an `ExprArena`.
+///
+/// Numbers are stored as their bit pattern so nodes can be hashed; this means
+/// `0.0` and `-0.0` are distinct nodes, while every `NaN` with the same bits
+/// shares one.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Node {
+    Binary {
+        op:  Operator,
+        lhs: ExprId,
+        rhs: ExprId,
+    },
+    Call {
+        name: String,
+        args: Vec<ExprId>,
+    },
+    Do(Vec<ExprId>),
+    For {
+        var:   String,
+        start: ExprId,
+        end:   ExprId,
+        step:  Option<ExprId>,
+        body:  ExprId,
+    },
+    If {
+        cond: ExprId,
+        then: ExprId,
+        alt:  ExprId,
+    },
+    NamedArg {
+        name:  String,
+        value: ExprId,
+    },
+    Number(u64),
+    Seq(Vec<ExprId>),
+    Tuple(Vec<ExprId>),
+    Var {
+        bindings: Vec<(String, Option<ExprId>)>,
+        body:     ExprId,
+    },
+    Variable(String),
+}
+
+/// Interns expressions so that structurally identical subtrees share a single
+/// node, e.g. both operands of `f(x) + f(x)` get the same `ExprId`.
+#[derive(Debug, Default)]
+pub struct ExprArena {
+    nodes: Vec<Node>,
+    ids:   HashMap<Node, ExprId>,
+}
+
+impl ExprArena {
+    /// Creates a new, empty `ExprArena`.
+    pub fn new() -> Self { Self::default() }
+
+    /// Returns the id of `node`, adding it to the arena if it isn't already
+    /// interned.
+    pub fn intern(&mut self, node: Node) -> ExprId {
+        if let Some(&id) = self.ids.get(&node) {
+            return id;
+        }
+
+        let id = ExprId(self.nodes.len());
+        self.nodes.push(node.clone());
+        self.ids.insert(node, id);
+        id
+    }
+
+    /// Interns `expr` along with all of its subexpressions, returning the id
+    /// of its root.
+    pub fn insert(&mut self, expr: &Expr) -> ExprId {
+        let node = match expr {
+            Expr::Binary { op, lhs, rhs, .. } => Node::Binary {
+                op:  *op,
+                lhs: self.insert(lhs),
+                rhs: self.insert(rhs),
+            },
+            Expr::Call { name, args, .. } => Node::Call {
+                name: name.clone(),
+                args: self.insert_all(args),
+            },
+            Expr::Do(stmts, _) => Node::Do(self.insert_all(stmts)),
+            Expr::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+                ..
+            } => Node::For {
+                var:   var.clone(),
+                start: self.insert(start),
+                end:   self.insert(end),
+                step:  step.as_deref().map(|step| self.insert(step)),
+                body:  self.insert(body),
+            },
+            Expr::If { cond, then, alt, .. } => Node::If {
+                cond: self.insert(cond),
+                then: self.insert(then),
+                alt:  self.insert(alt),
+            },
+            Expr::NamedArg { name, value, .. } => Node::NamedArg {
+                name:  name.clone(),
+                value: self.insert(value),
+            },
+            Expr::Number(value, _) => Node::Number(value.to_bits()),
+            Expr::Seq(exprs, _) => Node::Seq(self.insert_all(exprs)),
+            Expr::Tuple(elems, _) => Node::Tuple(self.insert_all(elems)),
+            Expr::Var { bindings, body, .. } => Node::Var {
+                bindings: bindings
+                    .iter()
+                    .map(|(name, init)| (name.clone(), init.as_ref().map(|init| self.insert(init))))
+                    .collect(),
+                body:     self.insert(body),
+            },
+            Expr::Variable(name, _) => Node::Variable(name.clone()),
+        };
+
+        self.intern(node)
+    }
+
+    /// Returns the node with the given id.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `id` comes from another arena and is out of bounds.
+    pub fn get(&self, id: ExprId) -> &Node { &self.nodes[id.0] }
+
+    /// Rebuilds the owned expression rooted at `id`. Nodes are shared between
+    /// the places they appear, so the expression has no spans.
+    pub fn to_expr(&self, id: ExprId) -> Expr {
+        let to_exprs = |ids: &[ExprId]| ids.iter().map(|&id| self.to_expr(id)).collect();
+
+        match self.get(id) {
+            Node::Binary { op, lhs, rhs } => Expr::Binary {
+                op:   *op,
+                lhs:  Box::new(self.to_expr(*lhs)),
+                rhs:  Box::new(self.to_expr(*rhs)),
+                span: Span::default(),
+            },
+            Node::Call { name, args } => Expr::Call {
+                name: name.clone(),
+                args: to_exprs(args),
+                span: Span::default(),
+            },
+            Node::Do(stmts) => Expr::Do(to_exprs(stmts), Span::default()),
+            Node::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+            } => Expr::For {
+                var:   var.clone(),
+                start: Box::new(self.to_expr(*start)),
+                end:   Box::new(self.to_expr(*end)),
+                step:  step.map(|step| Box::new(self.to_expr(step))),
+                body:  Box::new(self.to_expr(*body)),
+                span:  Span::default(),
+            },
+            Node::If { cond, then, alt } => Expr::If {
+                cond: Box::new(self.to_expr(*cond)),
+                then: Box::new(self.to_expr(*then)),
+                alt:  Box::new(self.to_expr(*alt)),
+                span: Span::default(),
+            },
+            Node::NamedArg { name, value } => Expr::NamedArg {
+                name:  name.clone(),
+                value: Box::new(self.to_expr(*value)),
+                span:  Span::default(),
+            },
+            Node::Number(bits) => Expr::Number(f64::from_bits(*bits), Span::default()),
+            Node::Seq(exprs) => Expr::Seq(to_exprs(exprs), Span::default()),
+            Node::Tuple(elems) => Expr::Tuple(to_exprs(elems), Span::default()),
+            Node::Var { bindings, body } => Expr::Var {
+                bindings: bindings
+                    .iter()
+                    .map(|(name, init)| (name.clone(), init.map(|init| self.to_expr(init))))
+                    .collect(),
+                body:     Box::new(self.to_expr(*body)),
+                span:     Span::default(),
+            },
+            Node::Variable(name) => Expr::Variable(name.clone(), Span::default()),
+        }
+    }
+
+    /// Returns the number of distinct nodes in the arena.
+    pub fn len(&self) -> usize { self.nodes.len() }
+
+    /// Returns a value indicating whether or not the arena has no nodes.
+    pub fn is_empty(&self) -> bool { self.nodes.is_empty() }
+
+    fn insert_all(&mut self, exprs: &[Expr]) -> Vec<ExprId> {
+        exprs.iter().map(|expr| self.insert(expr)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::parse_expr;
+
+    #[test]
+    fn shares_identical_subtrees() {
+        let mut arena = ExprArena::new();
+        let root = arena.insert(&parse_expr("f(x) + f(x)"));
+
+        let &Node::Binary { lhs, rhs, .. } = arena.get(root) else {
+            panic!("expected a binary node");
+        };
+        assert_eq!(lhs, rhs);
+        // `x`, `f(x)` and the sum.
+        assert_eq!(arena.len(), 3);
+        assert_eq!(arena.insert(&parse_expr("f(x)")), lhs);
+    }
+
+    #[test]
+    fn rebuilds_interned_expressions() {
+        let expr = parse_expr("var a = 1 in if a < 2 then f(a, a) else 0");
+        let mut arena = ExprArena::new();
+        let root = arena.insert(&expr);
+
+        assert_eq!(arena.to_expr(root), expr);
+    }
+}
@@ -1,37 +1,799 @@
 //! The Kaleidoscope Abstract Syntax Tree (aka Parse Tree)
 
+use core::fmt;
+
+use crate::operator::{Assoc, Operator};
+use crate::span::Span;
+
 /// ExprAST - Base for all expression nodes.
-#[derive(Debug, Clone, PartialEq)]
+///
+/// The `Debug` output is a compact tree (e.g. `Binary '+' -> [Number 1.0,
+/// Number 2.0]`) rather than the derived struct syntax, to keep test failures
+/// readable. The alternate form (`{:#?}`) prints one node per line, indented
+/// by depth.
+///
+/// Every node carries the span of the source it was parsed from. Spans are
+/// ignored by `==`, so a parsed tree equals one built by hand.
+#[derive(Clone)]
 #[non_exhaustive]
 pub enum Expr {
     Binary {
-        op:  char,
-        lhs: Box<Expr>,
-        rhs: Box<Expr>,
+        op:   Operator,
+        lhs:  Box<Expr>,
+        rhs:  Box<Expr>,
+        span: Span,
     },
     Call {
         name: String,
         args: Vec<Expr>,
+        span: Span,
+    },
+    /// A `do { ... }` block, whose statements are evaluated in order. It
+    /// evaluates to its last statement, or to `0.0` if it's empty.
+    Do(Vec<Expr>, Span),
+    /// A `for var = start, end, step in body` loop. The loop variable is in
+    /// scope in `end`, `step` and `body`, but not in `start`.
+    For {
+        var:   String,
+        start: Box<Expr>,
+        end:   Box<Expr>,
+        step:  Option<Box<Expr>>,
+        body:  Box<Expr>,
+        span:  Span,
+    },
+    /// An `if cond then then else alt` conditional.
+    If {
+        cond: Box<Expr>,
+        then: Box<Expr>,
+        alt:  Box<Expr>,
+        span: Span,
+    },
+    /// A `name = value` argument. Only valid directly inside the `args` of a
+    /// `Call`, until `analysis::resolve_named_args` turns it into a positional
+    /// argument.
+    NamedArg {
+        name:  String,
+        value: Box<Expr>,
+        span:  Span,
     },
-    Number(f64),
-    Variable(String),
+    Number(f64, Span),
+    /// A chain of expressions separated by `:`, as in `a : b : c`, evaluated
+    /// in order. It evaluates to its last expression.
+    Seq(Vec<Expr>, Span),
+    Tuple(Vec<Expr>, Span),
+    /// A `var a = 1, b in body` expression, introducing mutable variables in
+    /// `body`. Each initializer sees the bindings before it, and a missing one
+    /// defaults to `0.0`.
+    Var {
+        bindings: Vec<(String, Option<Expr>)>,
+        body:     Box<Expr>,
+        span:     Span,
+    },
+    Variable(String, Span),
+}
+
+impl Expr {
+    /// Returns the range of the source this expression was parsed from, or
+    /// an empty span at 0 if it was built by hand.
+    pub const fn span(&self) -> Span {
+        match self {
+            Self::Binary { span, .. }
+            | Self::Call { span, .. }
+            | Self::Do(_, span)
+            | Self::For { span, .. }
+            | Self::If { span, .. }
+            | Self::NamedArg { span, .. }
+            | Self::Number(_, span)
+            | Self::Seq(_, span)
+            | Self::Tuple(_, span)
+            | Self::Var { span, .. }
+            | Self::Variable(_, span) => *span,
+        }
+    }
+
+    /// Returns the operator and operands of a `Binary` node.
+    pub fn as_binary(&self) -> Option<(Operator, &Expr, &Expr)> {
+        match self {
+            Self::Binary { op, lhs, rhs, .. } => Some((*op, lhs, rhs)),
+            _ => None,
+        }
+    }
+
+    /// Returns the callee name and arguments of a `Call` node.
+    pub fn as_call(&self) -> Option<(&str, &[Expr])> {
+        match self {
+            Self::Call { name, args, .. } => Some((name, args)),
+            _ => None,
+        }
+    }
+
+    /// Returns the operator and operand of a `Call` node produced by a unary
+    /// operator, i.e. a call of `unary` followed by a single operator
+    /// character. A function like `unaryx` is called like any other.
+    pub fn as_unary(&self) -> Option<(char, &Expr)> {
+        let (name, [operand]) = self.as_call()? else {
+            return None;
+        };
+
+        let mut chars = name.strip_prefix("unary")?.chars();
+        match (chars.next(), chars.next()) {
+            (Some(op), None) if !op.is_alphanumeric() && op != '_' => Some((op, operand)),
+            _ => None,
+        }
+    }
+
+    /// Returns the value of a `Number` node.
+    pub const fn as_number(&self) -> Option<f64> {
+        match self {
+            Self::Number(value, _) => Some(*value),
+            _ => None,
+        }
+    }
+
+    /// Returns the name of a `Variable` node.
+    pub fn as_variable(&self) -> Option<&str> {
+        match self {
+            Self::Variable(name, _) => Some(name),
+            _ => None,
+        }
+    }
+
+    /// Returns a value indicating whether or not this expression can be
+    /// evaluated without any environment, i.e. it references no variables and
+    /// calls no functions.
+    pub fn is_constant(&self) -> bool {
+        match self {
+            Self::Binary { lhs, rhs, .. } => lhs.is_constant() && rhs.is_constant(),
+            Self::Call { .. } | Self::Variable(..) => false,
+            Self::Do(stmts, _) => stmts.iter().all(Self::is_constant),
+            Self::For {
+                start,
+                end,
+                step,
+                body,
+                ..
+            } => {
+                start.is_constant()
+                    && end.is_constant()
+                    && step.as_deref().is_none_or(Self::is_constant)
+                    && body.is_constant()
+            },
+            Self::If { cond, then, alt, .. } => {
+                cond.is_constant() && then.is_constant() && alt.is_constant()
+            },
+            Self::NamedArg { value, .. } => value.is_constant(),
+            Self::Number(..) => true,
+            Self::Seq(exprs, _) => exprs.iter().all(Self::is_constant),
+            Self::Tuple(elems, _) => elems.iter().all(Self::is_constant),
+            Self::Var { bindings, body, .. } => {
+                bindings
+                    .iter()
+                    .all(|(_, init)| init.as_ref().is_none_or(Self::is_constant))
+                    && body.is_constant()
+            },
+        }
+    }
+
+    /// Returns a copy of this expression with every `for` and `var` binder
+    /// named `from` renamed to `to`, along with the references in its scope,
+    /// e.g. to freshen the binders of a body before substituting it into
+    /// another function. References to a `from` that isn't bound within the
+    /// expression are left alone, as are callee names and the names of named
+    /// arguments.
+    ///
+    /// Returns `None` if the renaming would capture a variable: if a renamed
+    /// reference ends up under a binder named `to`, as when renaming `x` to
+    /// `i` in `var x in for i = 0, i < 3 in x`, or if a reference to another
+    /// `to` ends up under a renamed binder.
+    pub fn rename_var(&self, from: &str, to: &str) -> Option<Expr> {
+        if from == to {
+            return Some(self.clone());
+        }
+
+        self.rename_in(from, to, RenameScope::default())
+    }
+
+    /// Does the work of `rename_var` for an expression found in `scope`.
+    fn rename_in(&self, from: &str, to: &str, scope: RenameScope) -> Option<Expr> {
+        let rename = |expr: &Expr| expr.rename_in(from, to, scope);
+        let rename_all = |exprs: &[Expr]| exprs.iter().map(rename).collect::<Option<Vec<_>>>();
+        let binder = |name: &String| {
+            if name == from {
+                to.to_string()
+            } else {
+                name.clone()
+            }
+        };
+
+        let renamed = match self {
+            Self::Binary { op, lhs, rhs, span } => Self::Binary {
+                op:   *op,
+                lhs:  Box::new(rename(lhs)?),
+                rhs:  Box::new(rename(rhs)?),
+                span: *span,
+            },
+            Self::Call { name, args, span } => Self::Call {
+                name: name.clone(),
+                args: rename_all(args)?,
+                span: *span,
+            },
+            Self::Do(stmts, span) => Self::Do(rename_all(stmts)?, *span),
+            Self::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+                span,
+            } => {
+                let inner = scope.enter(var, from, to);
+                let rename_scoped = |expr: &Expr| expr.rename_in(from, to, inner);
+
+                Self::For {
+                    var:   binder(var),
+                    start: Box::new(rename(start)?),
+                    end:   Box::new(rename_scoped(end)?),
+                    step:  match step {
+                        Some(step) => Some(Box::new(rename_scoped(step)?)),
+                        None => None,
+                    },
+                    body:  Box::new(rename_scoped(body)?),
+                    span:  *span,
+                }
+            },
+            Self::If {
+                cond,
+                then,
+                alt,
+                span,
+            } => Self::If {
+                cond: Box::new(rename(cond)?),
+                then: Box::new(rename(then)?),
+                alt:  Box::new(rename(alt)?),
+                span: *span,
+            },
+            Self::NamedArg { name, value, span } => Self::NamedArg {
+                name:  name.clone(),
+                value: Box::new(rename(value)?),
+                span:  *span,
+            },
+            Self::Number(value, span) => Self::Number(*value, *span),
+            Self::Seq(exprs, span) => Self::Seq(rename_all(exprs)?, *span),
+            Self::Tuple(elems, span) => Self::Tuple(rename_all(elems)?, *span),
+            Self::Var { bindings, body, span } => {
+                // Each binding is in scope from the next initializer onward.
+                let mut inner = scope;
+                let mut renamed = Vec::with_capacity(bindings.len());
+                for (name, init) in bindings {
+                    let init = match init {
+                        Some(init) => Some(init.rename_in(from, to, inner)?),
+                        None => None,
+                    };
+                    inner = inner.enter(name, from, to);
+                    renamed.push((binder(name), init));
+                }
+
+                Self::Var {
+                    bindings: renamed,
+                    body:     Box::new(body.rename_in(from, to, inner)?),
+                    span:     *span,
+                }
+            },
+            Self::Variable(name, span) if name == from && scope.renaming => {
+                if scope.to_bound {
+                    return None;
+                }
+                Self::Variable(to.to_string(), *span)
+            },
+            Self::Variable(name, _) if name == to && scope.renaming && !scope.to_bound => return None,
+            Self::Variable(name, span) => Self::Variable(name.clone(), *span),
+        };
+
+        Some(renamed)
+    }
+
+    /// Returns the expression as an indented tree with one node per line,
+    /// like `{:#?}`, e.g. `Binary '+'\n  Number 1.0\n  Variable x`.
+    pub fn to_tree_string(&self) -> String { format!("{self:#?}") }
+
+    /// Returns the label of this node along with its children, or `None` for
+    /// leaf nodes.
+    fn debug_parts(&self) -> (String, Option<Vec<&Expr>>) {
+        match self {
+            Self::Binary { op, lhs, rhs, .. } => (format!("Binary {op:?}"), Some(vec![lhs, rhs])),
+            Self::Call { name, args, .. } => (format!("Call {name}"), Some(args.iter().collect())),
+            Self::Do(stmts, _) => ("Do".to_string(), Some(stmts.iter().collect())),
+            Self::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+                ..
+            } => (
+                format!("For {var}"),
+                Some(
+                    [Some(start), Some(end), step.as_ref(), Some(body)]
+                        .into_iter()
+                        .flatten()
+                        .map(|e| &**e)
+                        .collect(),
+                ),
+            ),
+            Self::If { cond, then, alt, .. } => ("If".to_string(), Some(vec![cond, then, alt])),
+            Self::NamedArg { name, value, .. } => (format!("NamedArg {name}"), Some(vec![value])),
+            Self::Number(value, _) => (format!("Number {value:?}"), None),
+            Self::Seq(exprs, _) => ("Seq".to_string(), Some(exprs.iter().collect())),
+            Self::Tuple(elems, _) => ("Tuple".to_string(), Some(elems.iter().collect())),
+            Self::Var { bindings, body, .. } => {
+                let names = bindings.iter().map(|(name, _)| name.as_str()).collect::<Vec<_>>();
+                let inits = bindings.iter().filter_map(|(_, init)| init.as_ref());
+                (
+                    format!("Var {}", names.join(", ")),
+                    Some(inits.chain([&**body]).collect()),
+                )
+            },
+            Self::Variable(name, _) => (format!("Variable {name}"), None),
+        }
+    }
+
+    fn fmt_tree(&self, f: &mut fmt::Formatter<'_>, depth: usize) -> fmt::Result {
+        let (label, children) = self.debug_parts();
+        write!(f, "{:indent$}{label}", "", indent = depth * 2)?;
+
+        for child in children.unwrap_or_default() {
+            writeln!(f)?;
+            child.fmt_tree(f, depth + 1)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Where `Expr::rename_in` is relative to the binders involved in a renaming.
+#[derive(Debug, Clone, Copy, Default)]
+struct RenameScope {
+    /// Whether or not `from` refers to a binder that is being renamed.
+    renaming: bool,
+    /// Whether or not `to` refers to a binder nested in the innermost renamed
+    /// one.
+    to_bound: bool,
+}
+
+impl RenameScope {
+    /// Returns the scope inside a binder of `name`.
+    fn enter(self, name: &str, from: &str, to: &str) -> Self {
+        if name == from {
+            Self {
+                renaming: true,
+                to_bound: false,
+            }
+        } else if name == to {
+            Self {
+                to_bound: true,
+                ..self
+            }
+        } else {
+            self
+        }
+    }
+}
+
+impl PartialEq for Expr {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (
+                Self::Binary { op, lhs, rhs, .. },
+                Self::Binary {
+                    op: op2,
+                    lhs: lhs2,
+                    rhs: rhs2,
+                    ..
+                },
+            ) => op == op2 && lhs == lhs2 && rhs == rhs2,
+            (
+                Self::Call { name, args, .. },
+                Self::Call {
+                    name: name2,
+                    args: args2,
+                    ..
+                },
+            ) => name == name2 && args == args2,
+            (Self::Do(stmts, _), Self::Do(stmts2, _)) => stmts == stmts2,
+            (
+                Self::For {
+                    var,
+                    start,
+                    end,
+                    step,
+                    body,
+                    ..
+                },
+                Self::For {
+                    var: var2,
+                    start: start2,
+                    end: end2,
+                    step: step2,
+                    body: body2,
+                    ..
+                },
+            ) => var == var2 && start == start2 && end == end2 && step == step2 && body == body2,
+            (
+                Self::If { cond, then, alt, .. },
+                Self::If {
+                    cond: cond2,
+                    then: then2,
+                    alt: alt2,
+                    ..
+                },
+            ) => cond == cond2 && then == then2 && alt == alt2,
+            (
+                Self::NamedArg { name, value, .. },
+                Self::NamedArg {
+                    name: name2,
+                    value: value2,
+                    ..
+                },
+            ) => name == name2 && value == value2,
+            (Self::Number(value, _), Self::Number(value2, _)) => value == value2,
+            (Self::Seq(exprs, _), Self::Seq(exprs2, _)) => exprs == exprs2,
+            (Self::Tuple(elems, _), Self::Tuple(elems2, _)) => elems == elems2,
+            (
+                Self::Var { bindings, body, .. },
+                Self::Var {
+                    bindings: bindings2,
+                    body: body2,
+                    ..
+                },
+            ) => bindings == bindings2 && body == body2,
+            (Self::Variable(name, _), Self::Variable(name2, _)) => name == name2,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Debug for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if f.alternate() {
+            return self.fmt_tree(f, 0);
+        }
+
+        let (label, children) = self.debug_parts();
+        f.write_str(&label)?;
+
+        match children {
+            Some(children) => {
+                f.write_str(" -> ")?;
+                f.debug_list().entries(children).finish()
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+/// Renders the expression as source with explicit grouping: every binary
+/// operation, conditional, loop and `var` is parenthesized, as in
+/// `((1 + 2) * x)`. See `pretty::render` for the minimal form.
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn list(f: &mut fmt::Formatter<'_>, exprs: &[Expr], sep: &str) -> fmt::Result {
+            for (i, expr) in exprs.iter().enumerate() {
+                if i > 0 {
+                    f.write_str(sep)?;
+                }
+                write!(f, "{expr}")?;
+            }
+            Ok(())
+        }
+
+        match self {
+            Self::Binary { op, lhs, rhs, .. } => write!(f, "({lhs} {op} {rhs})"),
+            Self::Call { name, args, .. } => match self.as_unary() {
+                // Calls produced by a unary operator render as the operator.
+                Some((op, operand)) => write!(f, "{op}{operand}"),
+                None => {
+                    write!(f, "{name}(")?;
+                    list(f, args, ", ")?;
+                    f.write_str(")")
+                },
+            },
+            Self::Do(stmts, _) if stmts.is_empty() => f.write_str("do {}"),
+            Self::Do(stmts, _) => {
+                f.write_str("do { ")?;
+                list(f, stmts, "; ")?;
+                f.write_str(" }")
+            },
+            Self::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+                ..
+            } => {
+                write!(f, "(for {var} = {start}, {end}")?;
+                if let Some(step) = step {
+                    write!(f, ", {step}")?;
+                }
+                write!(f, " in {body})")
+            },
+            Self::If { cond, then, alt, .. } => write!(f, "(if {cond} then {then} else {alt})"),
+            Self::NamedArg { name, value, .. } => write!(f, "{name} = {value}"),
+            Self::Number(value, _) => write!(f, "{value}"),
+            Self::Seq(exprs, _) => {
+                f.write_str("(")?;
+                list(f, exprs, " : ")?;
+                f.write_str(")")
+            },
+            Self::Tuple(elems, _) => {
+                f.write_str("(")?;
+                list(f, elems, ", ")?;
+                f.write_str(")")
+            },
+            Self::Var { bindings, body, .. } => {
+                f.write_str("(var ")?;
+                for (i, (name, init)) in bindings.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(", ")?;
+                    }
+                    f.write_str(name)?;
+                    if let Some(init) = init {
+                        write!(f, " = {init}")?;
+                    }
+                }
+                write!(f, " in {body})")
+            },
+            Self::Variable(name, _) => f.write_str(name),
+        }
+    }
 }
 
 /// PrototypeAST - This represents the "prototype" for a function,
 /// which captures its name, and its argument names (thus implicitly the number
 /// of arguments the function takes).
-#[derive(Debug, Clone, PartialEq)]
+///
+/// Like `Expr`, the span is ignored by `==`.
+#[derive(Debug, Clone)]
 pub struct Prototype {
-    pub name:  String,
-    pub args:  Vec<String>,
-    pub prec:  usize,
-    pub is_op: bool,
+    pub name:             String,
+    pub args:             Vec<String>,
+    pub prec:             usize,
+    /// The associativity of a `binary` operator, declared by `right` before
+    /// its precedence. Always `Left` for other functions.
+    pub assoc:            Assoc,
+    pub is_op:            bool,
+    /// The calling convention named by an `extern "..."` declaration. `None`
+    /// means the default C convention.
+    pub abi:              Option<String>,
+    /// The text of the comment on the same line right after the prototype,
+    /// as in `def f(x) # the comment` with the body on the lines below. The
+    /// comment trailing an `extern` belongs to its `Function` instead.
+    pub trailing_comment: Option<String>,
+    /// The range of the source the prototype was parsed from.
+    pub span:             Span,
+}
+
+impl PartialEq for Prototype {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+            && self.args == other.args
+            && self.prec == other.prec
+            && self.assoc == other.assoc
+            && self.is_op == other.is_op
+            && self.abi == other.abi
+            && self.trailing_comment == other.trailing_comment
+    }
+}
+
+impl Prototype {
+    /// Returns a value indicating whether or not `self` and `other` declare the
+    /// same function: the same name, number of arguments and operator-ness.
+    /// Unlike `==`, this ignores the precedence, so redeclaring `binary+` at
+    /// another precedence still names the same operator.
+    pub fn same_signature(&self, other: &Self) -> bool {
+        self.name == other.name && self.args.len() == other.args.len() && self.is_op == other.is_op
+    }
+}
+
+/// Renders the prototype as it's declared after `def` or `extern`, e.g.
+/// `f(a, b)` or `binary| 5 (a, b)`.
+impl fmt::Display for Prototype {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let args = self.args.join(", ");
+
+        match self.name.strip_prefix("binary") {
+            Some(_) if self.is_op && self.assoc == Assoc::Right => {
+                write!(f, "{} right {} ({args})", self.name, self.prec)
+            },
+            Some(_) if self.is_op => write!(f, "{} {} ({args})", self.name, self.prec),
+            _ => write!(f, "{}({args})", self.name),
+        }
+    }
 }
 
 /// FunctionAST - This represents a function definition itself.
-#[derive(Debug, Clone, PartialEq)]
+///
+/// A function without a `body` is either an `extern` or a forward declaration
+/// (`def f(x);`) that is defined later in the program. Like `Expr`, the span
+/// is ignored by `==`.
+#[derive(Debug, Clone)]
 pub struct Function {
-    pub proto:   Prototype,
-    pub body:    Option<Expr>,
-    pub is_anon: bool,
+    pub proto:            Prototype,
+    pub body:             Option<Expr>,
+    pub is_anon:          bool,
+    pub is_extern:        bool,
+    /// The text of the comments immediately preceding the `def` or `extern`
+    /// keyword, one line per comment.
+    pub doc:              Option<String>,
+    /// The text of the comment on the same line right after the item, as in
+    /// `def f(x) x + 1 # the comment`.
+    pub trailing_comment: Option<String>,
+    /// The range of the source from the `def` or `extern` keyword to the end
+    /// of the body, or of the prototype if there is none.
+    pub span:             Span,
+}
+
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.proto == other.proto
+            && self.body == other.body
+            && self.is_anon == other.is_anon
+            && self.is_extern == other.is_extern
+            && self.doc == other.doc
+            && self.trailing_comment == other.trailing_comment
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{parse_expr, parse_items};
+
+    #[test]
+    fn debug_is_compact() {
+        assert_eq!(
+            format!("{:?}", parse_expr("1 + 2 * x")),
+            "Binary '+' -> [Number 1.0, Binary '*' -> [Number 2.0, Variable x]]"
+        );
+    }
+
+    #[test]
+    fn constants_reference_no_variables_or_calls() {
+        assert!(parse_expr("3 * (1 + 2)").is_constant());
+        assert!(!parse_expr("1 + x").is_constant());
+        assert!(!parse_expr("f(1) * (2)").is_constant());
+    }
+
+    #[test]
+    fn same_signature_ignores_precedence() {
+        let items = parse_items("def binary| 10 (a, b) a; def binary| 20 (a, b) b");
+        let (low, high) = (&items[0].proto, &items[1].proto);
+
+        assert!(low.same_signature(high));
+        assert_ne!(low, high);
+    }
+
+    #[test]
+    fn accessors_match_their_node_only() {
+        let sum = parse_expr("x + 1");
+        let (op, lhs, rhs) = sum.as_binary().unwrap();
+        assert_eq!(op, '+');
+        assert_eq!(lhs.as_variable(), Some("x"));
+        assert_eq!(rhs.as_number(), Some(1.0));
+        assert!(sum.as_call().is_none());
+
+        let call = parse_expr("f(2)");
+        let (name, args) = call.as_call().unwrap();
+        assert_eq!((name, args.len()), ("f", 1));
+        assert!(call.as_binary().is_none());
+        assert!(call.as_number().is_none());
+        assert!(call.as_variable().is_none());
+    }
+
+    #[test]
+    fn rename_var_leaves_callee_names_alone() {
+        assert_eq!(
+            parse_expr("var x in x(x) * (x + y)").rename_var("x", "z"),
+            Some(parse_expr("var z in x(z) * (z + y)"))
+        );
+    }
+
+    fn rename(input: &str, from: &str, to: &str) -> Option<String> {
+        parse_expr(input)
+            .rename_var(from, to)
+            .map(|expr| expr.to_string())
+    }
+
+    #[test]
+    fn renames_for_loop_variables() {
+        assert_eq!(
+            rename("for i = 0, i < 3 in i", "i", "j").as_deref(),
+            Some("(for j = 0, (j < 3) in j)")
+        );
+    }
+
+    #[test]
+    fn renaming_leaves_outer_same_named_variables_alone() {
+        // The start is outside the loop variable's scope.
+        assert_eq!(
+            rename("i + (for i = i, i < 3 in i)", "i", "j").as_deref(),
+            Some("(i + (for j = i, (j < 3) in j))")
+        );
+    }
+
+    #[test]
+    fn renaming_refuses_to_capture() {
+        assert_eq!(rename("var x in for i = 0, i < 3 in x", "x", "i"), None);
+        assert_eq!(rename("for x = 0, x < 3 in x + i", "x", "i"), None);
+    }
+
+    #[test]
+    fn displays_expressions_fully_parenthesized() {
+        for (input, expected) in [
+            ("1 + 2 * 3", "(1 + (2 * 3))"),
+            ("(a - b) - c", "((a - b) - c)"),
+            ("a - (b - c)", "(a - (b - c))"),
+            ("f(x, 2.5) - 1", "(f(x, 2.5) - 1)"),
+            ("-3", "-3"),
+        ] {
+            assert_eq!(parse_expr(input).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn displays_prototypes() {
+        for (input, expected) in [
+            ("def f(a, b) a", "f(a, b)"),
+            ("extern g();", "g()"),
+            ("def binary| 5 (a, b) a", "binary| 5 (a, b)"),
+        ] {
+            let items = parse_items(input);
+            assert_eq!(items[0].proto.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn renders_only_operator_calls_as_unary_operators() {
+        for input in ["unaryx(5)", "unary_(5)", "unary2(a)"] {
+            assert_eq!(parse_expr(input).to_string(), input);
+            assert_eq!(parse_expr(&parse_expr(input).to_string()), parse_expr(input));
+            assert_eq!(parse_expr(input).as_unary(), None);
+        }
+
+        let call = Expr::Call {
+            name: "unary!".to_string(),
+            args: vec![parse_expr("x")],
+            span: Span::default(),
+        };
+        assert_eq!(call.to_string(), "!x");
+        assert_eq!(call.as_unary(), Some(('!', &parse_expr("x"))));
+    }
+
+    #[test]
+    fn binary_spans_cover_both_operands() {
+        let expr = parse_expr("  foo + 12");
+        assert_eq!(expr.span(), Span::new(2, 10));
+
+        let (_, lhs, rhs) = expr.as_binary().unwrap();
+        assert_eq!(lhs.span(), Span::new(2, 5));
+        assert_eq!(rhs.span(), Span::new(8, 10));
+    }
+
+    #[test]
+    fn equality_ignores_spans() {
+        assert_eq!(parse_expr("a + 1"), parse_expr("  a  +  1"));
+        assert_ne!(parse_expr("a + 1"), parse_expr("a + 2"));
+    }
+
+    #[test]
+    fn renders_an_indented_tree() {
+        assert_eq!(
+            parse_expr("1 + foo(x)").to_tree_string(),
+            "Binary '+'\n  Number 1.0\n  Call foo\n    Variable x"
+        );
+        assert_eq!(
+            parse_expr("if a then -1 else b").to_tree_string(),
+            "If\n  Variable a\n  Number -1.0\n  Variable b"
+        );
+    }
 }
@@ -0,0 +1,528 @@
+//! LLVM IR generation for parsed programs
+
+use std::collections::HashMap;
+use std::ffi::{CStr, CString};
+
+use inkwell::builder::Builder;
+use inkwell::context::Context;
+use inkwell::intrinsics::Intrinsic;
+use inkwell::llvm_sys::error::{LLVMDisposeErrorMessage, LLVMGetErrorMessage};
+use inkwell::llvm_sys::transforms::pass_builder::LLVMRunPassesOnFunction;
+use inkwell::module::Module;
+use inkwell::passes::PassBuilderOptions;
+use inkwell::targets::{CodeModel, InitializationConfig, RelocMode, Target, TargetMachine};
+use inkwell::types::BasicMetadataTypeEnum;
+use inkwell::values::{AsValueRef as _, BasicMetadataValueEnum, FloatValue, FunctionValue};
+use inkwell::{FloatPredicate, OptimizationLevel};
+
+use crate::ast::{Expr, Function, Prototype};
+use crate::error::{Error, Result};
+use crate::operator::Operator;
+
+/// The LLVM id of the C calling convention, used when a prototype has no
+/// `abi`.
+const CALL_CONV_C: u32 = 0;
+
+/// Returns the LLVM id of the calling convention named by an `extern "..."`
+/// declaration, if it's a known one.
+fn call_convention(abi: &str) -> Option<u32> {
+    match abi {
+        "C" => Some(CALL_CONV_C),
+        "fast" => Some(8),
+        "cold" => Some(9),
+        "preserve_most" => Some(14),
+        "preserve_all" => Some(15),
+        "stdcall" => Some(64),
+        "fastcall" => Some(65),
+        "thiscall" => Some(70),
+        "sysv64" => Some(78),
+        "win64" => Some(79),
+        "vectorcall" => Some(80),
+        _ => None,
+    }
+}
+
+/// Undoes the definition of `function` after its body failed to compile, so
+/// that no half-built function is left behind. If it was `declared` before,
+/// other functions may already call it, so only its body is removed and it
+/// becomes a declaration again; otherwise it's deleted.
+fn discard_body(function: FunctionValue<'_>, declared: bool) {
+    if declared {
+        for block in function.get_basic_blocks().into_iter().rev() {
+            // SAFETY: the blocks were all appended by the failed definition,
+            // and only they refer to each other's instructions. Deleting the
+            // last block first leaves no reference to a deleted one.
+            let _ = unsafe { block.delete() };
+        }
+    } else {
+        // SAFETY: nothing but `function`'s own body refers to it, since it was
+        // declared by the failed definition.
+        unsafe { function.delete() };
+    }
+}
+
+/// Compiles functions into an LLVM module, where every value is an `f64`.
+pub struct Compiler<'ctx> {
+    context:      &'ctx Context,
+    module:       Module<'ctx>,
+    builder:      Builder<'ctx>,
+    /// The parameters of the function being compiled.
+    named_values: HashMap<String, FloatValue<'ctx>>,
+    opt_level:    u8,
+    /// The machine the optimization passes target, created by the first
+    /// function they run on.
+    machine:      Option<TargetMachine>,
+}
+
+impl<'ctx> Compiler<'ctx> {
+    /// Creates a new `Compiler`, emitting code into a new module called
+    /// `name`.
+    pub fn new(context: &'ctx Context, name: &str) -> Self {
+        Self {
+            context,
+            module: context.create_module(name),
+            builder: context.create_builder(),
+            named_values: HashMap::new(),
+            opt_level: 0,
+            machine: None,
+        }
+    }
+
+    /// Sets the optimization level of the compiled functions. At level 0, the
+    /// default, they are left as generated; at any other level they go
+    /// through the passes from chapter 4 of the tutorial.
+    #[must_use]
+    pub const fn with_optimizations(mut self, level: u8) -> Self {
+        self.opt_level = level;
+        self
+    }
+
+    /// Returns the module the functions are compiled into.
+    pub const fn module(&self) -> &Module<'ctx> { &self.module }
+
+    /// Runs `function`, the compiled anonymous function of a top-level
+    /// expression, with a JIT and returns its result. The function is removed
+    /// from the module afterward, so the next top-level expression can reuse
+    /// its name.
+    pub fn run_toplevel(&mut self, function: FunctionValue<'ctx>) -> Result<f64> {
+        let name = function.get_name().to_string_lossy().into_owned();
+
+        // An execution engine takes ownership of its module, so it runs a copy
+        // that the following definitions won't be added to.
+        let module = self.module.clone();
+
+        // SAFETY: `function` is only referred to by the copy of the module
+        // made above.
+        unsafe { function.delete() };
+
+        let engine = module
+            .create_jit_execution_engine(OptimizationLevel::None)
+            .map_err(|err| Error::Jit(err.to_string()))?;
+
+        // SAFETY: every compiled function takes `f64`s and returns an `f64`,
+        // and anonymous functions take no parameters.
+        unsafe {
+            let toplevel = engine
+                .get_function::<unsafe extern "C" fn() -> f64>(&name)
+                .map_err(|err| Error::Jit(err.to_string()))?;
+            Ok(toplevel.call())
+        }
+    }
+
+    /// Emits the code computing `expr` at the current position of the
+    /// builder.
+    pub fn compile_expr(&mut self, expr: &Expr) -> Result<FloatValue<'ctx>> {
+        match expr {
+            Expr::Number(value, _) => Ok(self.context.f64_type().const_float(*value)),
+            Expr::Variable(name, _) => self
+                .named_values
+                .get(name)
+                .copied()
+                .ok_or_else(|| Error::Codegen(format!("Unknown variable `{name}`."))),
+            // Variables are SSA values rather than stack slots, so there is
+            // nothing to store into yet.
+            Expr::Binary { op, .. } if op.as_char() == Some('=') => Err(Error::Codegen(format!(
+                "Assignment in `{expr}` is not supported by the compiler."
+            ))),
+            Expr::Binary { op, lhs, rhs, .. } => {
+                let lhs = self.compile_expr(lhs)?;
+                let rhs = self.compile_expr(rhs)?;
+
+                match op.as_char() {
+                    Some('+') => Ok(self.builder.build_float_add(lhs, rhs, "addtmp")?),
+                    Some('-') => Ok(self.builder.build_float_sub(lhs, rhs, "subtmp")?),
+                    Some('*') => Ok(self.builder.build_float_mul(lhs, rhs, "multmp")?),
+                    Some('/') => Ok(self.builder.build_float_div(lhs, rhs, "divtmp")?),
+                    Some('%') => Ok(self.builder.build_float_rem(lhs, rhs, "remtmp")?),
+                    Some('<') => self.build_compare(FloatPredicate::OLT, lhs, rhs),
+                    Some('>') => self.build_compare(FloatPredicate::OGT, lhs, rhs),
+                    Some('^') => {
+                        // Declare the intrinsic, then call it like any function.
+                        let pow = Intrinsic::find("llvm.pow")
+                            .and_then(|pow| {
+                                pow.get_declaration(&self.module, &[self.context.f64_type().into()])
+                            })
+                            .ok_or_else(|| Error::Codegen("Could not declare `llvm.pow`.".to_string()))?;
+                        self.build_call(&pow.get_name().to_string_lossy(), &[lhs, rhs])
+                    },
+                    None if *op == Operator::pair('<', '=') => {
+                        self.build_compare(FloatPredicate::OLE, lhs, rhs)
+                    },
+                    None if *op == Operator::pair('>', '=') => {
+                        self.build_compare(FloatPredicate::OGE, lhs, rhs)
+                    },
+                    None if *op == Operator::pair('=', '=') => {
+                        self.build_compare(FloatPredicate::OEQ, lhs, rhs)
+                    },
+                    None if *op == Operator::pair('!', '=') => {
+                        self.build_compare(FloatPredicate::ONE, lhs, rhs)
+                    },
+                    // Any other operator must have been defined with `binary`.
+                    _ => self.build_call(&format!("binary{op}"), &[lhs, rhs]),
+                }
+            },
+            Expr::Call { name, args, .. } => {
+                let args = args
+                    .iter()
+                    .map(|arg| self.compile_expr(arg))
+                    .collect::<Result<Vec<_>>>()?;
+                self.build_call(name, &args)
+            },
+            Expr::Seq(exprs, _) => exprs
+                .iter()
+                .try_fold(self.context.f64_type().const_zero(), |_, expr| {
+                    self.compile_expr(expr)
+                }),
+            _ => Err(Error::Codegen(format!(
+                "`{expr}` is not supported by the compiler."
+            ))),
+        }
+    }
+
+    /// Emits a comparison of `lhs` and `rhs`, converting the result to `1.0`
+    /// if it holds and `0.0` otherwise.
+    fn build_compare(
+        &self,
+        predicate: FloatPredicate,
+        lhs: FloatValue<'ctx>,
+        rhs: FloatValue<'ctx>,
+    ) -> Result<FloatValue<'ctx>> {
+        let cmp = self.builder.build_float_compare(predicate, lhs, rhs, "cmptmp")?;
+        let f64_type = self.context.f64_type();
+        Ok(self
+            .builder
+            .build_unsigned_int_to_float(cmp, f64_type, "booltmp")?)
+    }
+
+    /// Emits a call to the function `name` with compiled arguments.
+    fn build_call(&self, name: &str, args: &[FloatValue<'ctx>]) -> Result<FloatValue<'ctx>> {
+        let callee = self
+            .module
+            .get_function(name)
+            .ok_or_else(|| Error::Codegen(format!("Unknown function `{name}`.")))?;
+
+        if callee.count_params() as usize != args.len() {
+            return Err(Error::Codegen(format!(
+                "Function `{name}` takes {} arguments, but {} were given.",
+                callee.count_params(),
+                args.len()
+            )));
+        }
+
+        let args: Vec<BasicMetadataValueEnum> = args.iter().map(|&arg| arg.into()).collect();
+        let call = self.builder.build_call(callee, &args, "calltmp")?;
+        // A call must use the convention the callee was declared with.
+        call.set_call_convention(callee.get_call_conventions());
+        call.try_as_basic_value()
+            .basic()
+            .map(|value| value.into_float_value())
+            .ok_or_else(|| Error::Codegen(format!("Call to `{name}` produced no value.")))
+    }
+
+    /// Declares the function described by `proto` in the module, with the
+    /// calling convention named by its `abi`.
+    pub fn compile_prototype(&self, proto: &Prototype) -> Result<FunctionValue<'ctx>> {
+        let call_conv = match &proto.abi {
+            Some(abi) => call_convention(abi).ok_or_else(|| {
+                Error::Codegen(format!(
+                    "Unknown calling convention `{abi}` for function `{}`.",
+                    proto.name
+                ))
+            })?,
+            None => CALL_CONV_C,
+        };
+
+        let f64_type = self.context.f64_type();
+        let params: Vec<BasicMetadataTypeEnum> = proto.args.iter().map(|_| f64_type.into()).collect();
+        let function = self
+            .module
+            .add_function(&proto.name, f64_type.fn_type(&params, false), None);
+        function.set_call_conventions(call_conv);
+
+        for (param, name) in function.get_param_iter().zip(&proto.args) {
+            param.into_float_value().set_name(name);
+        }
+
+        Ok(function)
+    }
+
+    /// Compiles `func` into the module. Externs and forward declarations only
+    /// declare the function; a definition reuses an earlier declaration of it.
+    pub fn compile_fn(&mut self, func: &Function) -> Result<FunctionValue<'ctx>> {
+        let declared = self.module.get_function(&func.proto.name);
+        let function = match declared {
+            Some(function) => function,
+            None => self.compile_prototype(&func.proto)?,
+        };
+
+        let Some(body) = &func.body else {
+            return Ok(function);
+        };
+
+        if function.count_basic_blocks() > 0 {
+            return Err(Error::Codegen(format!(
+                "Function `{}` cannot be redefined.",
+                func.proto.name
+            )));
+        }
+
+        let entry = self.context.append_basic_block(function, "entry");
+        self.builder.position_at_end(entry);
+
+        self.named_values.clear();
+        for (param, name) in function.get_param_iter().zip(&func.proto.args) {
+            self.named_values.insert(name.clone(), param.into_float_value());
+        }
+
+        let result = self.compile_expr(body).and_then(|value| {
+            self.builder.build_return(Some(&value))?;
+            Ok(())
+        });
+
+        if let Err(err) = result {
+            discard_body(function, declared.is_some());
+            return Err(err);
+        }
+
+        if !function.verify(true) {
+            discard_body(function, declared.is_some());
+            return Err(Error::Codegen(format!(
+                "Function `{}` is invalid.",
+                func.proto.name
+            )));
+        }
+
+        if self.opt_level > 0 {
+            self.optimize(function)?;
+        }
+
+        Ok(function)
+    }
+
+    /// Runs the optimization passes over `function`. The functions compiled
+    /// before it were already optimized, so they're left alone.
+    fn optimize(&mut self, function: FunctionValue<'ctx>) -> Result<()> {
+        let machine = self.target_machine()?;
+        let passes = CString::new("instcombine,reassociate,gvn,simplifycfg").expect("No pass has a NUL.");
+        let options = PassBuilderOptions::create();
+
+        // SAFETY: `function`, `machine` and `options` all outlive the call.
+        let error = unsafe {
+            LLVMRunPassesOnFunction(
+                function.as_value_ref(),
+                passes.as_ptr(),
+                machine.as_mut_ptr(),
+                options.as_mut_ptr(),
+            )
+        };
+        if error.is_null() {
+            return Ok(());
+        }
+
+        // SAFETY: `error` is a non-null error, which getting its message
+        // consumes. The message is copied before it's disposed of.
+        let message = unsafe {
+            let raw = LLVMGetErrorMessage(error);
+            let message = CStr::from_ptr(raw).to_string_lossy().into_owned();
+            LLVMDisposeErrorMessage(raw);
+            message
+        };
+        Err(Error::Codegen(message))
+    }
+
+    /// Returns the machine to optimize for, creating it the first time.
+    fn target_machine(&mut self) -> Result<&TargetMachine> {
+        if self.machine.is_none() {
+            Target::initialize_native(&InitializationConfig::default()).map_err(Error::Codegen)?;
+
+            let triple = TargetMachine::get_default_triple();
+            let machine = Target::from_triple(&triple)
+                .map_err(|err| Error::Codegen(err.to_string()))?
+                .create_target_machine(
+                    &triple,
+                    "generic",
+                    "",
+                    OptimizationLevel::None,
+                    RelocMode::PIC,
+                    CodeModel::Default,
+                )
+                .ok_or_else(|| Error::Codegen("Could not create a target machine.".to_string()))?;
+            self.machine = Some(machine);
+        }
+
+        Ok(self.machine.as_ref().expect("The machine was just created."))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use inkwell::values::AnyValue as _;
+
+    use super::*;
+    use crate::testing::parse_items;
+
+    fn compiler() -> Compiler<'static> { Compiler::new(Box::leak(Box::new(Context::create())), "test") }
+
+    /// Compiles every item of `input` and returns the value of its last
+    /// top-level expression.
+    fn run(input: &str) -> Result<f64> {
+        let mut compiler = compiler();
+        let mut value = 0.0;
+        for func in parse_items(input) {
+            let function = compiler.compile_fn(&func)?;
+            if func.is_anon {
+                value = compiler.run_toplevel(function)?;
+            }
+        }
+        Ok(value)
+    }
+
+    #[test]
+    fn sets_calling_convention_from_abi() {
+        let mut compiler = compiler();
+        let funcs = parse_items(r#"extern "fast" f(x); extern g(x); extern "pascal" h(x)"#);
+        assert_eq!(compiler.compile_fn(&funcs[0]).unwrap().get_call_conventions(), 8);
+        assert_eq!(
+            compiler.compile_fn(&funcs[1]).unwrap().get_call_conventions(),
+            CALL_CONV_C
+        );
+        assert!(compiler.compile_fn(&funcs[2]).is_err());
+    }
+
+    #[test]
+    fn compiles_remainder() {
+        assert_eq!(run("7 % 3").unwrap(), 1.0);
+    }
+
+    #[test]
+    fn compiles_comparisons() {
+        assert_eq!(run("3 > 2").unwrap(), 1.0);
+        assert_eq!(run("2 >= 2").unwrap(), 1.0);
+        assert_eq!(run("1 <= 0").unwrap(), 0.0);
+        // Ordered, like the interpreter: nothing compares with NaN.
+        assert_eq!(run("0/0 <= 1").unwrap(), 0.0);
+        assert_eq!(run("0/0 >= 1").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn compiles_functions_to_ir() {
+        let mut compiler = compiler();
+        let add = compiler.compile_fn(&parse_items("def add(a, b) a+b")[0]).unwrap();
+
+        assert_eq!(add.count_params(), 2);
+        assert!(add.print_to_string().to_string().contains("fadd"));
+    }
+
+    #[test]
+    fn keeps_declarations_whose_definition_fails() {
+        let mut compiler = compiler();
+        let items = parse_items("extern sin(x); def g(x) sin(x); def sin(x) y; def sin(x) x * 2; g(3)");
+        compiler.compile_fn(&items[0]).unwrap();
+        let g = compiler.compile_fn(&items[1]).unwrap();
+        assert!(compiler.compile_fn(&items[2]).is_err());
+
+        // `g` still calls the declaration, which can be defined later.
+        let sin = compiler.module().get_function("sin").unwrap();
+        assert_eq!(sin.count_basic_blocks(), 0);
+        assert!(g.verify(false));
+        compiler.compile_fn(&items[3]).unwrap();
+        let toplevel = compiler.compile_fn(&items[4]).unwrap();
+        assert_eq!(compiler.run_toplevel(toplevel).unwrap(), 6.0);
+
+        // A function that wasn't declared before is removed altogether.
+        assert!(compiler.compile_fn(&parse_items("def f(x) y")[0]).is_err());
+        assert!(compiler.module().get_function("f").is_none());
+    }
+
+    #[test]
+    fn rejects_unknown_variables() {
+        let mut compiler = compiler();
+        let err = compiler.compile_fn(&parse_items("def f(x) y")[0]).unwrap_err();
+        assert!(matches!(err, Error::Codegen(_)), "{err}");
+    }
+
+    #[test]
+    fn runs_toplevel_expressions() {
+        assert_eq!(run("4+5;").unwrap(), 9.0);
+
+        // Each anonymous function is removed after it runs, so the next one can
+        // take its name, and definitions stay callable.
+        let mut compiler = compiler();
+        let mut values = vec![];
+        for func in parse_items("def sq(x) x*x; sq(3); sq(4)") {
+            let function = compiler.compile_fn(&func).unwrap();
+            if func.is_anon {
+                values.push(compiler.run_toplevel(function).unwrap());
+            }
+        }
+        assert_eq!(values, [9.0, 16.0]);
+    }
+
+    fn count_instructions(function: FunctionValue<'_>) -> usize {
+        function
+            .get_basic_blocks()
+            .into_iter()
+            .map(|block| block.get_instructions().count())
+            .sum()
+    }
+
+    #[test]
+    fn optimizations_shrink_functions() {
+        let func = &parse_items("def f(x) x*2+x*2")[0];
+
+        let plain = count_instructions(compiler().compile_fn(func).unwrap());
+        let optimized = count_instructions(compiler().with_optimizations(1).compile_fn(func).unwrap());
+        assert!(optimized < plain, "{optimized} >= {plain}");
+    }
+
+    #[test]
+    fn compiles_less_and_greater_than() {
+        assert_eq!(run("3 < 4").unwrap(), 1.0);
+        assert_eq!(run("5 > 9").unwrap(), 0.0);
+        assert_eq!(run("0/0 < 1").unwrap(), 0.0);
+        assert_eq!(run("0/0 > 1").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn rejects_assignment() {
+        let err = run("def f(x) x = 1").unwrap_err();
+        assert!(matches!(err, Error::Codegen(_)), "{err}");
+    }
+
+    #[test]
+    fn compiles_powers() {
+        assert_eq!(run("2 ^ 3 ^ 2").unwrap(), 512.0);
+    }
+
+    #[test]
+    fn optimizes_each_function_once_for_one_machine() {
+        let mut compiler = compiler().with_optimizations(1);
+        let f = compiler.compile_fn(&parse_items("def f(x) x*2+x*2")[0]).unwrap();
+        let machine = compiler.machine.as_ref().unwrap().as_mut_ptr();
+        let optimized = count_instructions(f);
+
+        compiler.compile_fn(&parse_items("def g(x) f(x) + 1")[0]).unwrap();
+        assert_eq!(compiler.machine.as_ref().unwrap().as_mut_ptr(), machine);
+        assert_eq!(count_instructions(f), optimized);
+    }
+}
@@ -0,0 +1,86 @@
+//! Rendering of errors alongside the source they refer to
+
+use crate::span::Span;
+
+/// Renders `message` followed by the line of `source` containing the start of
+/// `span`, with the span underlined by carets, in the style of rustc:
+///
+/// ```text
+/// error: Expected ')' in function call.
+///  --> 2:7
+///   |
+/// 2 |   f(x y)
+///   |       ^
+/// ```
+///
+/// A span covering several lines is only underlined on its first line. If
+/// `span` lies outside of `source`, only the message is rendered.
+pub fn render(source: &str, span: Span, message: &str) -> String {
+    let Some(before) = source.get(..span.start) else {
+        return format!("error: {message}\n");
+    };
+
+    let line_start = before.rfind('\n').map_or(0, |i| i + 1);
+    let line_end = source[span.start..]
+        .find('\n')
+        .map_or(source.len(), |i| span.start + i);
+    let line = source[line_start..line_end].trim_end_matches('\r');
+
+    let number = before.matches('\n').count() + 1;
+    let column = before[line_start..].chars().count() + 1;
+
+    // Underline at least one character, so that empty spans (e.g. at the end
+    // of the input) are still pointed at.
+    // A span may start past the end of the line, at the `\r\n` ending it.
+    let end = span.end.min(line_start + line.len()).max(span.start);
+    let width = source
+        .get(span.start..end)
+        .map_or(0, |s| s.chars().count())
+        .max(1);
+
+    let gutter = " ".repeat(number.to_string().len());
+    format!(
+        "error: {message}\n{gutter}--> {number}:{column}\n{gutter} |\n{number} | {line}\n{gutter} | {}{}\n",
+        " ".repeat(column - 1),
+        "^".repeat(width),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn underlines_the_span_on_its_line() {
+        let source = "def f(x)\n  f(x y)\n";
+        let rendered = render(source, Span::new(15, 16), "Expected ')' in function call.");
+        assert_eq!(
+            rendered,
+            "error: Expected ')' in function call.\n --> 2:7\n  |\n2 |   f(x y)\n  |       ^\n"
+        );
+    }
+
+    #[test]
+    fn underlines_only_the_first_line_of_a_span() {
+        let rendered = render("a +\nb", Span::new(0, 5), "Oops.");
+        assert_eq!(rendered, "error: Oops.\n --> 1:1\n  |\n1 | a +\n  | ^^^\n");
+    }
+
+    #[test]
+    fn renders_only_the_message_outside_the_source() {
+        assert_eq!(render("x", Span::new(5, 6), "Oops."), "error: Oops.\n");
+    }
+
+    #[test]
+    fn underlines_spans_at_the_end_of_crlf_lines() {
+        let source = "a +\r\nb";
+        assert_eq!(
+            render(source, Span::new(4, 5), "Oops."),
+            "error: Oops.\n --> 1:5\n  |\n1 | a +\n  |     ^\n"
+        );
+        assert_eq!(
+            render(source, Span::new(3, 6), "Oops."),
+            "error: Oops.\n --> 1:4\n  |\n1 | a +\n  |    ^\n"
+        );
+    }
+}
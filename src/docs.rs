@@ -0,0 +1,32 @@
+//! Documentation extraction from Kaleidoscope source
+
+use crate::ast::Function;
+
+/// Pairs the name of each definition and extern in `program` with its
+/// documentation, i.e. the comments written directly above it. Functions
+/// without comments are paired with an empty string; anonymous top-level
+/// expressions are skipped.
+pub fn extract(program: &[Function]) -> Vec<(String, String)> {
+    program
+        .iter()
+        .filter(|func| !func.is_anon)
+        .map(|func| (func.proto.name.clone(), func.doc.clone().unwrap_or_default()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::parse_items;
+
+    #[test]
+    fn extracts_leading_comments() {
+        let input = "# Adds one\n# to x.\ndef inc(x) x + 1\n\n# Not attached.\n\ndef id(x) x";
+        let program = parse_items(input);
+
+        assert_eq!(extract(&program), [
+            ("inc".to_string(), "Adds one\nto x.".to_string()),
+            ("id".to_string(), String::new()),
+        ]);
+    }
+}
@@ -0,0 +1,203 @@
+//! The Kaleidoscope error type
+
+use core::fmt;
+use std::io;
+
+use crate::span::Span;
+
+/// A `Result` whose error is the crate's `Error`.
+pub type Result<T> = core::result::Result<T, Error>;
+
+/// An error raised by any stage of the pipeline. Lexer, parse and runtime
+/// errors may point at the source location they refer to.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    Lexer {
+        message: String,
+        span:    Option<Span>,
+    },
+    Parse {
+        kind:     ParseErrorKind,
+        message:  String,
+        span:     Option<Span>,
+        /// The 1-based line and column of the start of `span`.
+        position: Option<(usize, usize)>,
+    },
+    /// The input has nothing to parse, only whitespace and comments.
+    Empty,
+    Codegen(String),
+    Jit(String),
+    Runtime {
+        message: String,
+        span:    Option<Span>,
+    },
+    Io(io::Error),
+}
+
+/// The reason for a parse error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParseErrorKind {
+    /// The input doesn't follow the grammar.
+    Syntax,
+    /// The input ended in the middle of an item.
+    Eof,
+}
+
+impl Error {
+    /// Creates a lexer error without a location.
+    pub fn lexer(message: impl Into<String>) -> Self {
+        Self::Lexer {
+            message: message.into(),
+            span:    None,
+        }
+    }
+
+    /// Creates a syntax error without a location.
+    pub fn parse(message: impl Into<String>) -> Self {
+        Self::Parse {
+            kind:     ParseErrorKind::Syntax,
+            message:  message.into(),
+            span:     None,
+            position: None,
+        }
+    }
+
+    /// Creates a runtime error without a location.
+    pub fn runtime(message: impl Into<String>) -> Self {
+        Self::Runtime {
+            message: message.into(),
+            span:    None,
+        }
+    }
+
+    /// Attaches `span` to a lexer, parse or runtime error, replacing any
+    /// location it had. Other variants are returned unchanged.
+    #[must_use]
+    pub fn with_span(mut self, span: Span) -> Self {
+        if let Self::Lexer { span: slot, .. }
+        | Self::Parse { span: slot, .. }
+        | Self::Runtime { span: slot, .. } = &mut self
+        {
+            *slot = Some(span);
+        }
+        self
+    }
+
+    /// Returns the source location of the error, if known.
+    pub const fn span(&self) -> Option<Span> {
+        match self {
+            Self::Lexer { span, .. } | Self::Parse { span, .. } | Self::Runtime { span, .. } => *span,
+            _ => None,
+        }
+    }
+
+    /// Returns the error message, without the location or kind prefix.
+    pub fn message(&self) -> String {
+        match self {
+            Self::Lexer { message, .. } | Self::Parse { message, .. } | Self::Runtime { message, .. } => {
+                message.clone()
+            },
+            Self::Codegen(message) | Self::Jit(message) => message.clone(),
+            Self::Empty => "The input is empty.".to_string(),
+            Self::Io(err) => err.to_string(),
+        }
+    }
+
+    const fn kind(&self) -> &'static str {
+        match self {
+            Self::Lexer { .. } => "lexer",
+            Self::Parse { .. } | Self::Empty => "parse",
+            Self::Codegen(_) => "codegen",
+            Self::Jit(_) => "jit",
+            Self::Runtime { .. } => "runtime",
+            Self::Io(_) => "io",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Self::Parse {
+            position: Some((line, column)),
+            ..
+        } = self
+        {
+            return write!(f, "{} error at {line}:{column}: {}", self.kind(), self.message());
+        }
+
+        match self.span() {
+            Some(Span { start, end }) => {
+                write!(f, "{} error at {start}..{end}: {}", self.kind(), self.message())
+            },
+            None => write!(f, "{} error: {}", self.kind(), self.message()),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Self { Self::Io(err) }
+}
+
+#[cfg(feature = "llvm")]
+impl From<inkwell::builder::BuilderError> for Error {
+    fn from(err: inkwell::builder::BuilderError) -> Self { Self::Codegen(err.to_string()) }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::error::Error as _;
+
+    use super::*;
+
+    #[test]
+    fn displays_span_when_known() {
+        let err = Error::lexer("Unexpected `$`.");
+        assert_eq!(err.to_string(), "lexer error: Unexpected `$`.");
+
+        let err = err.with_span(Span::new(3, 4));
+        assert_eq!(err.span(), Some(Span::new(3, 4)));
+        assert_eq!(err.message(), "Unexpected `$`.");
+        assert_eq!(err.to_string(), "lexer error at 3..4: Unexpected `$`.");
+    }
+
+    #[test]
+    fn displays_every_variant() {
+        for (err, expected) in [
+            (Error::parse("Expected ')'."), "parse error: Expected ')'."),
+            (Error::Empty, "parse error: The input is empty."),
+            (
+                Error::Codegen("Invalid call.".to_string()),
+                "codegen error: Invalid call.",
+            ),
+            (Error::Jit("No engine.".to_string()), "jit error: No engine."),
+            (
+                Error::runtime("Unknown variable `x`."),
+                "runtime error: Unknown variable `x`.",
+            ),
+            (
+                Error::runtime("Unknown variable `x`.").with_span(Span::new(0, 1)),
+                "runtime error at 0..1: Unknown variable `x`.",
+            ),
+            (io::Error::other("Broken pipe.").into(), "io error: Broken pipe."),
+        ] {
+            assert_eq!(err.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn io_errors_are_the_source() {
+        let err = Error::from(io::Error::other("Broken pipe."));
+        assert_eq!(err.source().unwrap().to_string(), "Broken pipe.");
+        assert!(Error::parse("Expected ')'.").source().is_none());
+    }
+}
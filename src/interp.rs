@@ -0,0 +1,1439 @@
+//! A tree-walking interpreter for parsed programs
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::ast::{Expr, Function, Prototype};
+use crate::error::{Error, Result};
+use crate::operator::{Assoc, Operator};
+use crate::span::Span;
+
+/// A native function callable from Kaleidoscope, given the interpreter's
+/// output and its arguments.
+pub type Builtin = fn(&mut dyn Write, &[f64]) -> f64;
+
+/// The deepest function calls may nest before evaluation fails, instead of
+/// overflowing the native stack.
+pub const MAX_CALL_DEPTH: usize = 200;
+
+/// The number of iterations the `for` loops of a top-level evaluation may
+/// run in total by default; see [`Interp::with_max_iterations`].
+pub const DEFAULT_MAX_ITERATIONS: usize = 10_000_000;
+
+/// A value computed by the interpreter.
+///
+/// Like compiled code, comparisons evaluate to `1.0` or `0.0` and loops to
+/// `0.0`, so a program that never builds a tuple only ever sees numbers.
+/// Variables and arguments must be numbers. Conditions may be numbers, true
+/// when non-zero, or booleans.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Number(f64),
+    Bool(bool),
+    Tuple(Vec<Value>),
+    Unit,
+}
+
+impl Value {
+    /// Returns whether the value holds as a condition, or `None` if it isn't a
+    /// number or a boolean.
+    pub const fn is_true(&self) -> Option<bool> {
+        match self {
+            Self::Number(value) => Some(*value != 0.0),
+            Self::Bool(value) => Some(*value),
+            Self::Tuple(_) | Self::Unit => None,
+        }
+    }
+}
+
+impl From<f64> for Value {
+    fn from(value: f64) -> Self { Self::Number(value) }
+}
+
+impl PartialEq<f64> for Value {
+    fn eq(&self, other: &f64) -> bool { matches!(self, Self::Number(value) if value == other) }
+}
+
+/// Renders numbers like `f64`s, booleans as `true` or `false`, tuples like
+/// their expressions, e.g. `(1, 2)`, and the unit value as `()`.
+impl fmt::Display for Value {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Number(value) => write!(f, "{value}"),
+            Self::Bool(value) => write!(f, "{value}"),
+            Self::Unit => write!(f, "()"),
+            Self::Tuple(values) => {
+                write!(f, "(")?;
+                for (i, value) in values.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{value}")?;
+                }
+                write!(f, ")")
+            },
+        }
+    }
+}
+
+/// The outcome of a call to [`Interp::eval_step`].
+#[derive(Debug)]
+pub enum StepResult {
+    /// `expr`, a node of the expression being stepped through, evaluated to
+    /// `value`. A node is reported after all of its operands, so the whole
+    /// expression comes last.
+    Evaluated { expr: Expr, value: Value },
+    /// The expression evaluated to `value`, and there is nothing left to step
+    /// through.
+    Done(Value),
+    /// Evaluation failed, or there was nothing to step through.
+    Failed(Error),
+}
+
+/// A pending piece of work of an evaluation started with
+/// [`Interp::start_eval`]. Values are passed along on `Interp::values`.
+#[derive(Debug)]
+enum Task {
+    /// Evaluates the expression, leaving its value on the stack.
+    Eval(Expr),
+    /// Fails unless the value on top of the stack, that of the expression,
+    /// is a number.
+    ExpectNumber(Expr),
+    /// Finishes evaluating the expression from the values of its operands on
+    /// top of the stack.
+    Apply(Expr),
+    /// Reports the expression as evaluated to the value on top of the stack.
+    Report(Expr),
+    /// Drops the value on top of the stack.
+    Discard,
+    /// Binds `name`, if any, to the number on top of the stack, then
+    /// evaluates the next of the `rest` of the bindings of a `var`, or its
+    /// `body` once they are all bound.
+    Bind {
+        name: Option<String>,
+        rest: std::vec::IntoIter<(String, Option<Expr>)>,
+        body: Expr,
+    },
+    /// Restores the variable `name` that a `var` or `for` shadowed.
+    Restore { name: String, shadowed: Option<f64> },
+    /// Binds the variable of a `for` loop to the number on top of the stack.
+    Enter(Rc<Loop>),
+    /// Runs an iteration of a `for` loop.
+    Iterate(Rc<Loop>),
+    /// Steps the variable of a `for` loop and runs it again, unless the end
+    /// condition on the stack is zero.
+    Advance(Rc<Loop>),
+    /// Returns from a call made at `span`, restoring the caller's scope.
+    Return {
+        caller: HashMap<String, f64>,
+        span:   Span,
+    },
+}
+
+/// The parts of a `for` loop that each of its iterations evaluates.
+#[derive(Debug)]
+struct Loop {
+    var:  String,
+    end:  Expr,
+    step: Option<Expr>,
+    body: Expr,
+    span: Span,
+}
+
+/// Evaluates expressions directly on the AST, without compiling them.
+///
+/// Values are described by [`Value`]. Functions must be `define`d before they
+/// are called, except for the builtins `putchard` and `printd`; each call
+/// evaluates the callee's body in a fresh scope holding only its parameters.
+/// Builtins write to standard output, unless given another writer with
+/// [`Interp::with_output`].
+///
+/// An expression may also be evaluated one node at a time, with
+/// [`Interp::start_eval`] and [`Interp::eval_step`].
+pub struct Interp {
+    env:            HashMap<String, f64>,
+    funcs:          HashMap<String, Rc<Function>>,
+    builtins:       HashMap<String, (usize, Builtin)>,
+    depth:          usize,
+    iterations:     usize,
+    max_iterations: usize,
+    out:            Box<dyn Write>,
+    /// The work left in the evaluation being stepped through, last first.
+    tasks:          Vec<Task>,
+    /// The values computed so far by the evaluation being stepped through.
+    values:         Vec<Value>,
+}
+
+impl Default for Interp {
+    fn default() -> Self {
+        let mut interp = Self::without_builtins();
+        interp.define_builtin("putchard", 1, putchard);
+        interp.define_builtin("printd", 1, printd);
+        interp
+    }
+}
+
+impl fmt::Debug for Interp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Interp")
+            .field("env", &self.env)
+            .field("funcs", &self.funcs)
+            .field("builtins", &self.builtins.keys())
+            .field("depth", &self.depth)
+            .field("iterations", &self.iterations)
+            .field("max_iterations", &self.max_iterations)
+            .field("tasks", &self.tasks)
+            .field("values", &self.values)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Interp {
+    /// Creates a new `Interp` with no variables, and only the builtin
+    /// functions.
+    pub fn new() -> Self { Self::default() }
+
+    /// Creates a new `Interp` with no variables and no functions at all, not
+    /// even the builtins.
+    pub fn without_builtins() -> Self {
+        Self {
+            env:            HashMap::new(),
+            funcs:          HashMap::new(),
+            builtins:       HashMap::new(),
+            depth:          0,
+            iterations:     0,
+            max_iterations: DEFAULT_MAX_ITERATIONS,
+            out:            Box::new(io::stdout()),
+            tasks:          Vec::new(),
+            values:         Vec::new(),
+        }
+    }
+
+    /// Makes builtins write to `out` instead of standard output.
+    pub fn with_output(mut self, out: impl Write + 'static) -> Self {
+        self.out = Box::new(out);
+        self
+    }
+
+    /// Makes evaluation fail once its `for` loops have run more than `max`
+    /// iterations in total, instead of the default
+    /// [`DEFAULT_MAX_ITERATIONS`], so that a loop whose condition never
+    /// becomes false cannot hang the caller. Iterations are counted across
+    /// all loops, nested or not, and the count starts over with each call to
+    /// [`Interp::eval`].
+    pub fn with_max_iterations(mut self, max: usize) -> Self {
+        self.max_iterations = max;
+        self
+    }
+
+    /// Makes the native `func`, taking `arity` arguments, callable by name.
+    /// Builtins take precedence over functions with the same name, so that
+    /// an `extern` declaring them doesn't hide them.
+    pub fn define_builtin(&mut self, name: &str, arity: usize, func: Builtin) {
+        self.builtins.insert(name.to_string(), (arity, func));
+    }
+
+    /// Returns the prototype of each builtin, in no particular order, with
+    /// its arguments named `x0`, `x1`, and so on.
+    pub fn builtins(&self) -> impl Iterator<Item = Prototype> + '_ {
+        self.builtins.iter().map(|(name, &(arity, _))| Prototype {
+            name:             name.clone(),
+            args:             (0..arity).map(|i| format!("x{i}")).collect(),
+            prec:             0,
+            assoc:            Assoc::Left,
+            is_op:            false,
+            abi:              None,
+            trailing_comment: None,
+            span:             Span::default(),
+        })
+    }
+
+    /// Makes `func` callable by name, replacing any function with the same
+    /// name.
+    pub fn define(&mut self, func: Function) { self.funcs.insert(func.proto.name.clone(), Rc::new(func)); }
+
+    /// Runs `program`, returning the value of each of its top-level
+    /// expressions, and `None` for its other items. Evaluation stops at the
+    /// first error.
+    ///
+    /// Items run in order, like compiled code, so a definition replaces the
+    /// function of the same name for the items after it. Functions that
+    /// aren't defined yet are made callable up front, so that they may be
+    /// called before they're defined.
+    pub fn eval_program(&mut self, program: &[Function]) -> Result<Vec<Option<Value>>> {
+        self.define_ahead(program);
+        program.iter().map(|func| self.eval_item(func)).collect()
+    }
+
+    /// Makes each function defined in `program` callable, unless a function
+    /// with the same name already is. Running the items of `program` in turn
+    /// with [`Interp::eval_item`] then behaves like [`Interp::eval_program`].
+    pub fn define_ahead(&mut self, program: &[Function]) {
+        for func in program.iter().filter(|func| !func.is_anon && func.body.is_some()) {
+            if !self.funcs.contains_key(&func.proto.name) {
+                self.define(func.clone());
+            }
+        }
+    }
+
+    /// Runs `item`, returning its value if it's a top-level expression. A
+    /// definition is made callable, and declarations are ignored.
+    pub fn eval_item(&mut self, item: &Function) -> Result<Option<Value>> {
+        match &item.body {
+            Some(body) if item.is_anon => self.eval(body).map(Some),
+            Some(_) => {
+                self.define(item.clone());
+                Ok(None)
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Evaluates `expr` in the current scope, as a new top-level evaluation.
+    pub fn eval(&mut self, expr: &Expr) -> Result<Value> {
+        self.iterations = 0;
+        self.eval_expr(expr)
+    }
+
+    /// Starts evaluating `expr` as a new top-level evaluation, to be carried
+    /// out by [`Interp::eval_step`]. Any evaluation already being stepped
+    /// through is abandoned.
+    pub fn start_eval(&mut self, expr: &Expr) {
+        self.unwind();
+        self.iterations = 0;
+        self.tasks.push(Task::Eval(expr.clone()));
+    }
+
+    /// Advances the evaluation started by [`Interp::start_eval`] until it has
+    /// evaluated one more node, and reports that node and its value. Operands
+    /// are evaluated before their operators and a function's body as part of
+    /// its call, as with [`Interp::eval`], and the results are the same.
+    ///
+    /// Once the whole expression has been reported, the next step returns
+    /// [`StepResult::Done`] with its value. A failed evaluation stops, with
+    /// the scopes it entered left, and the error is pointed at the outermost
+    /// call it happened in, as with [`Interp::eval`].
+    pub fn eval_step(&mut self) -> StepResult {
+        while let Some(task) = self.tasks.pop() {
+            match self.run_task(task) {
+                Ok(Some((expr, value))) => return StepResult::Evaluated { expr, value },
+                Ok(None) => (),
+                Err(err) => {
+                    return StepResult::Failed(match self.unwind() {
+                        Some(span) => err.with_span(span),
+                        None => err,
+                    });
+                },
+            }
+        }
+
+        match self.values.pop() {
+            Some(value) => StepResult::Done(value),
+            None => StepResult::Failed(Error::runtime("There is no evaluation to step through.")),
+        }
+    }
+
+    /// Evaluates `expr` as part of the current top-level evaluation.
+    fn eval_expr(&mut self, expr: &Expr) -> Result<Value> {
+        match expr {
+            Expr::Number(value, _) => Ok(Value::Number(*value)),
+            Expr::Variable(name, span) => self
+                .env
+                .get(name)
+                .copied()
+                .map(Value::Number)
+                .ok_or_else(|| Error::runtime(format!("Unknown variable `{name}`.")).with_span(*span)),
+            Expr::Binary { op, lhs, rhs, .. } if op.as_char() == Some('=') => {
+                self.assign(lhs, rhs).map(Value::Number)
+            },
+            Expr::Binary { op, lhs, rhs, span } => {
+                let lhs = self.eval_number(lhs)?;
+                let rhs = self.eval_number(rhs)?;
+                self.binary(*op, lhs, rhs, *span)
+            },
+            Expr::Call { name, args, span } => self.eval_call(name, args, *span),
+            Expr::Do(exprs, _) | Expr::Seq(exprs, _) => exprs
+                .iter()
+                .try_fold(Value::Number(0.0), |_, expr| self.eval_expr(expr)),
+            Expr::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+                span,
+            } => self
+                .eval_for(var, start, end, step.as_deref(), body, *span)
+                .map(Value::Number),
+            Expr::If { cond, then, alt, .. } => {
+                if self.eval_condition(cond)? {
+                    self.eval_expr(then)
+                } else {
+                    self.eval_expr(alt)
+                }
+            },
+            Expr::Tuple(elems, _) => elems
+                .iter()
+                .map(|elem| self.eval_expr(elem))
+                .collect::<Result<_>>()
+                .map(Value::Tuple),
+            Expr::Var { bindings, body, .. } => self.eval_var(bindings, body),
+            Expr::NamedArg { span, .. } => Err(Error::runtime(format!(
+                "The named argument `{expr}` must be resolved before it's evaluated."
+            ))
+            .with_span(*span)),
+        }
+    }
+
+    /// Evaluates the call of `name` at `span` with `args`.
+    fn eval_call(&mut self, name: &str, args: &[Expr], span: Span) -> Result<Value> {
+        let args = args
+            .iter()
+            .map(|arg| self.eval_number(arg))
+            .collect::<Result<Vec<_>>>()?;
+        self.call(name, &args).map_err(|err| err.with_span(span))
+    }
+
+    /// Evaluates `expr`, which must be a number.
+    fn eval_number(&mut self, expr: &Expr) -> Result<f64> {
+        match self.eval_expr(expr)? {
+            Value::Number(value) => Ok(value),
+            value => Err(not_a_number(expr, &value)),
+        }
+    }
+
+    /// Evaluates the condition `expr`, which must be a number or a boolean.
+    fn eval_condition(&mut self, expr: &Expr) -> Result<bool> {
+        let value = self.eval_expr(expr)?;
+        value.is_true().ok_or_else(|| not_a_condition(expr, &value))
+    }
+
+    /// Evaluates a `for` loop the way the tutorial compiles it: `body` runs
+    /// with `var` set to `start`, then again after each `step` (`1.0` by
+    /// default) for as long as `end` is non-zero. `end` is evaluated after the
+    /// body and before the step, so the body always runs at least once. The
+    /// loop evaluates to `0.0`, and the variable `var` shadows is restored
+    /// afterward, even if evaluation fails. Running out of iterations is
+    /// reported at `span`.
+    fn eval_for(
+        &mut self,
+        var: &str,
+        start: &Expr,
+        end: &Expr,
+        step: Option<&Expr>,
+        body: &Expr,
+        span: Span,
+    ) -> Result<f64> {
+        let start = self.eval_number(start)?;
+        let shadowed = self.env.insert(var.to_string(), start);
+
+        let result = self.run_loop(var, end, step, body, span);
+
+        match shadowed {
+            Some(value) => self.env.insert(var.to_string(), value),
+            None => self.env.remove(var),
+        };
+
+        result.map(|()| 0.0)
+    }
+
+    /// Runs the iterations of a `for` loop whose variable `var` is already in
+    /// scope, failing at `span` once the iterations of every loop run so far
+    /// add up to more than the maximum.
+    fn run_loop(
+        &mut self,
+        var: &str,
+        end: &Expr,
+        step: Option<&Expr>,
+        body: &Expr,
+        span: Span,
+    ) -> Result<()> {
+        loop {
+            self.count_iteration(span)?;
+
+            self.eval_expr(body)?;
+            let step = step.map_or(Ok(1.0), |step| self.eval_number(step))?;
+            if self.eval_number(end)? == 0.0 {
+                return Ok(());
+            }
+
+            if let Some(value) = self.env.get_mut(var) {
+                *value += step;
+            }
+        }
+    }
+
+    /// Counts an iteration of the loop at `span`, failing there if it's one
+    /// more than the maximum.
+    fn count_iteration(&mut self, span: Span) -> Result<()> {
+        if self.iterations == self.max_iterations {
+            return Err(Error::runtime(format!(
+                "Loop iteration limit of {} exceeded.",
+                self.max_iterations
+            ))
+            .with_span(span));
+        }
+        self.iterations += 1;
+        Ok(())
+    }
+
+    /// Evaluates `target = value`, storing the value of `value` into the
+    /// variable `target` and returning it.
+    fn assign(&mut self, target: &Expr, value: &Expr) -> Result<f64> {
+        let Expr::Variable(name, span) = target else {
+            return Err(
+                Error::runtime(format!("Cannot assign to `{target}`, which is not a variable."))
+                    .with_span(target.span()),
+            );
+        };
+
+        let value = self.eval_number(value)?;
+        let slot = self
+            .env
+            .get_mut(name)
+            .ok_or_else(|| Error::runtime(format!("Unknown variable `{name}`.")).with_span(*span))?;
+        *slot = value;
+
+        Ok(value)
+    }
+
+    /// Evaluates `body` with `bindings` in scope. The variables they shadow
+    /// are restored afterward, even if evaluation fails.
+    fn eval_var(&mut self, bindings: &[(String, Option<Expr>)], body: &Expr) -> Result<Value> {
+        let mut shadowed = Vec::with_capacity(bindings.len());
+        let mut result = Ok(Value::Number(0.0));
+
+        for (name, init) in bindings {
+            let value = match init.as_ref().map_or(Ok(0.0), |init| self.eval_number(init)) {
+                Ok(value) => value,
+                Err(err) => {
+                    result = Err(err);
+                    break;
+                },
+            };
+            shadowed.push((name, self.env.insert(name.clone(), value)));
+        }
+
+        if result.is_ok() {
+            result = self.eval_expr(body);
+        }
+
+        for (name, previous) in shadowed.into_iter().rev() {
+            match previous {
+                Some(value) => self.env.insert(name.clone(), value),
+                None => self.env.remove(name),
+            };
+        }
+
+        result
+    }
+
+    /// Applies a binary operator. Operators other than the built-in ones call
+    /// the matching `binary` function, as if it were called at `span`.
+    fn binary(&mut self, op: Operator, lhs: f64, rhs: f64, span: Span) -> Result<Value> {
+        match builtin_binary(op, lhs, rhs) {
+            Some(value) => Ok(Value::Number(value)),
+            None => self
+                .call(&format!("binary{op}"), &[lhs, rhs])
+                .map_err(|err| err.with_span(span)),
+        }
+    }
+
+    /// Calls the function `name` with already evaluated arguments. Calls
+    /// nested deeper than [`MAX_CALL_DEPTH`] fail.
+    ///
+    /// The callee's body may come from another input than the call, so
+    /// [`Interp::eval`] points every error this returns at the call instead,
+    /// including those raised in the body.
+    fn call(&mut self, name: &str, args: &[f64]) -> Result<Value> {
+        let func = match self.callee(name, args)? {
+            Callee::Builtin(builtin) => return Ok(Value::Number(builtin(&mut *self.out, args))),
+            Callee::Function(func) => func,
+        };
+        let body = func.body.as_ref().expect("checked by `callee`");
+
+        let caller = core::mem::replace(&mut self.env, scope(&func, args));
+        self.depth += 1;
+        let result = self.eval_expr(body);
+        self.depth -= 1;
+        self.env = caller;
+
+        result
+    }
+
+    /// Looks up the function `name`, and checks that it may be called with
+    /// `args` from the current depth.
+    fn callee(&self, name: &str, args: &[f64]) -> Result<Callee> {
+        if let Some(&(arity, builtin)) = self.builtins.get(name) {
+            check_arity(name, arity, args)?;
+            return Ok(Callee::Builtin(builtin));
+        }
+
+        let func = self
+            .funcs
+            .get(name)
+            .cloned()
+            .ok_or_else(|| Error::runtime(format!("Unknown function `{name}`.")))?;
+
+        check_arity(name, func.proto.args.len(), args)?;
+
+        if func.body.is_none() {
+            return Err(Error::runtime(format!("Function `{name}` has no body.")));
+        }
+
+        if self.depth == MAX_CALL_DEPTH {
+            return Err(Error::runtime(format!(
+                "Calling `{name}` exceeds the maximum call depth of {MAX_CALL_DEPTH}."
+            )));
+        }
+
+        Ok(Callee::Function(func))
+    }
+
+    /// Runs `task` of a stepped evaluation, returning the node it finished
+    /// evaluating, if any, and its value.
+    fn run_task(&mut self, task: Task) -> Result<Option<(Expr, Value)>> {
+        match task {
+            Task::Eval(expr) => return self.start_node(expr),
+            Task::ExpectNumber(expr) => match self.values.last() {
+                Some(Value::Number(_)) => (),
+                Some(value) => return Err(not_a_number(&expr, value)),
+                None => unreachable!("the value of `{expr}` is on the stack"),
+            },
+            Task::Apply(expr) => return self.finish_node(expr),
+            Task::Report(expr) => {
+                let value = self
+                    .values
+                    .last()
+                    .cloned()
+                    .expect("the value of a node is on the stack");
+                return Ok(Some((expr, value)));
+            },
+            Task::Discard => {
+                self.values.pop();
+            },
+            Task::Bind { name, mut rest, body } => {
+                if let Some(name) = name {
+                    let value = self.pop_number();
+                    let shadowed = self.env.insert(name.clone(), value);
+                    self.tasks.push(Task::Restore { name, shadowed });
+                }
+
+                match rest.next() {
+                    Some((name, init)) => {
+                        self.tasks.push(Task::Bind {
+                            name: Some(name),
+                            rest,
+                            body,
+                        });
+                        match init {
+                            Some(init) => self.push_number(init),
+                            None => self.values.push(Value::Number(0.0)),
+                        }
+                    },
+                    None => self.tasks.push(Task::Eval(body)),
+                }
+            },
+            Task::Restore { name, shadowed } => self.restore(name, shadowed),
+            Task::Enter(for_loop) => {
+                let start = self.pop_number();
+                let shadowed = self.env.insert(for_loop.var.clone(), start);
+                self.tasks.push(Task::Restore {
+                    name: for_loop.var.clone(),
+                    shadowed,
+                });
+                self.tasks.push(Task::Iterate(for_loop));
+            },
+            Task::Iterate(for_loop) => {
+                self.count_iteration(for_loop.span)?;
+
+                // The body runs first, then the step and the end condition
+                // are evaluated, as in `run_loop`.
+                self.tasks.push(Task::Advance(for_loop.clone()));
+                self.push_number(for_loop.end.clone());
+                if let Some(step) = &for_loop.step {
+                    self.push_number(step.clone());
+                }
+                self.tasks.push(Task::Discard);
+                self.tasks.push(Task::Eval(for_loop.body.clone()));
+            },
+            Task::Advance(for_loop) => {
+                let end = self.pop_number();
+                let step = if for_loop.step.is_some() {
+                    self.pop_number()
+                } else {
+                    1.0
+                };
+
+                if end == 0.0 {
+                    self.values.push(Value::Number(0.0));
+                } else {
+                    if let Some(value) = self.env.get_mut(&for_loop.var) {
+                        *value += step;
+                    }
+                    self.tasks.push(Task::Iterate(for_loop));
+                }
+            },
+            Task::Return { caller, .. } => {
+                self.env = caller;
+                self.depth -= 1;
+            },
+        }
+
+        Ok(None)
+    }
+
+    /// Schedules the evaluation of `expr`, or evaluates it right away if it
+    /// has no operands.
+    fn start_node(&mut self, expr: Expr) -> Result<Option<(Expr, Value)>> {
+        match &expr {
+            Expr::Number(value, _) => {
+                let value = Value::Number(*value);
+                self.values.push(value.clone());
+                return Ok(Some((expr, value)));
+            },
+            Expr::Variable(name, span) => {
+                let value = self.env.get(name).copied().map(Value::Number).ok_or_else(|| {
+                    Error::runtime(format!("Unknown variable `{name}`.")).with_span(*span)
+                })?;
+                self.values.push(value.clone());
+                return Ok(Some((expr, value)));
+            },
+            Expr::Binary { op, lhs, rhs, .. } if op.as_char() == Some('=') => {
+                if !matches!(**lhs, Expr::Variable(..)) {
+                    return Err(Error::runtime(format!(
+                        "Cannot assign to `{lhs}`, which is not a variable."
+                    ))
+                    .with_span(lhs.span()));
+                }
+                let rhs = (**rhs).clone();
+                self.tasks.push(Task::Apply(expr));
+                self.push_number(rhs);
+            },
+            Expr::Binary { lhs, rhs, .. } => {
+                let (lhs, rhs) = ((**lhs).clone(), (**rhs).clone());
+                self.tasks.push(Task::Apply(expr));
+                self.push_number(rhs);
+                self.push_number(lhs);
+            },
+            Expr::Call { args, .. } => {
+                let args = args.clone();
+                self.tasks.push(Task::Apply(expr));
+                for arg in args.into_iter().rev() {
+                    self.push_number(arg);
+                }
+            },
+            Expr::Do(exprs, _) | Expr::Seq(exprs, _) if exprs.is_empty() => {
+                self.values.push(Value::Number(0.0));
+                return Ok(Some((expr, Value::Number(0.0))));
+            },
+            Expr::Do(exprs, _) | Expr::Seq(exprs, _) => {
+                let exprs = exprs.clone();
+                self.tasks.push(Task::Report(expr));
+                for (i, expr) in exprs.into_iter().rev().enumerate() {
+                    // Only the value of the last expression is kept.
+                    if i > 0 {
+                        self.tasks.push(Task::Discard);
+                    }
+                    self.tasks.push(Task::Eval(expr));
+                }
+            },
+            Expr::For {
+                var,
+                start,
+                end,
+                step,
+                body,
+                span,
+            } => {
+                let for_loop = Rc::new(Loop {
+                    var:  var.clone(),
+                    end:  (**end).clone(),
+                    step: step.as_deref().cloned(),
+                    body: (**body).clone(),
+                    span: *span,
+                });
+                let start = (**start).clone();
+                self.tasks.push(Task::Report(expr));
+                self.tasks.push(Task::Enter(for_loop));
+                self.push_number(start);
+            },
+            Expr::If { cond, .. } => {
+                let cond = (**cond).clone();
+                self.tasks.push(Task::Apply(expr));
+                self.tasks.push(Task::Eval(cond));
+            },
+            Expr::Tuple(elems, _) => {
+                let elems = elems.clone();
+                self.tasks.push(Task::Apply(expr));
+                self.tasks.extend(elems.into_iter().rev().map(Task::Eval));
+            },
+            Expr::Var { bindings, body, .. } => {
+                let bind = Task::Bind {
+                    name: None,
+                    rest: bindings.clone().into_iter(),
+                    body: (**body).clone(),
+                };
+                self.tasks.push(Task::Report(expr));
+                self.tasks.push(bind);
+            },
+            Expr::NamedArg { span, .. } => {
+                return Err(Error::runtime(format!(
+                    "The named argument `{expr}` must be resolved before it's evaluated."
+                ))
+                .with_span(*span));
+            },
+        }
+
+        Ok(None)
+    }
+
+    /// Finishes evaluating `expr` from the values of its operands.
+    fn finish_node(&mut self, expr: Expr) -> Result<Option<(Expr, Value)>> {
+        let value = match &expr {
+            Expr::Binary { op, lhs, .. } if op.as_char() == Some('=') => {
+                let Expr::Variable(name, span) = &**lhs else {
+                    unreachable!("checked by `start_node`");
+                };
+                let value = self.pop_number();
+                let slot = self.env.get_mut(name).ok_or_else(|| {
+                    Error::runtime(format!("Unknown variable `{name}`.")).with_span(*span)
+                })?;
+                *slot = value;
+                Value::Number(value)
+            },
+            Expr::Binary { op, span, .. } => {
+                let rhs = self.pop_number();
+                let lhs = self.pop_number();
+                match builtin_binary(*op, lhs, rhs) {
+                    Some(value) => Value::Number(value),
+                    None => {
+                        let name = format!("binary{op}");
+                        let span = *span;
+                        return self.start_call(&name, &[lhs, rhs], span, expr);
+                    },
+                }
+            },
+            Expr::Call { name, args, span } => {
+                let mut args: Vec<_> = args.iter().map(|_| self.pop_number()).collect();
+                args.reverse();
+                let (name, span) = (name.clone(), *span);
+                return self.start_call(&name, &args, span, expr);
+            },
+            Expr::If { cond, then, alt, .. } => {
+                let value = self.values.pop().expect("the condition is on the stack");
+                let branch = match value.is_true() {
+                    Some(true) => (**then).clone(),
+                    Some(false) => (**alt).clone(),
+                    None => return Err(not_a_condition(cond, &value)),
+                };
+                self.tasks.push(Task::Report(expr));
+                self.tasks.push(Task::Eval(branch));
+                return Ok(None);
+            },
+            Expr::Tuple(elems, _) => Value::Tuple(self.values.split_off(self.values.len() - elems.len())),
+            _ => unreachable!("`{expr}` is finished by `start_node`"),
+        };
+
+        self.values.push(value.clone());
+        Ok(Some((expr, value)))
+    }
+
+    /// Calls `name` at `span` with `args` as part of a stepped evaluation.
+    /// The call of a builtin finishes `expr` right away; that of a function
+    /// schedules its body, and finishes `expr` once the body is evaluated.
+    fn start_call(
+        &mut self,
+        name: &str,
+        args: &[f64],
+        span: Span,
+        expr: Expr,
+    ) -> Result<Option<(Expr, Value)>> {
+        let func = match self.callee(name, args).map_err(|err| err.with_span(span))? {
+            Callee::Builtin(builtin) => {
+                let value = Value::Number(builtin(&mut *self.out, args));
+                self.values.push(value.clone());
+                return Ok(Some((expr, value)));
+            },
+            Callee::Function(func) => func,
+        };
+
+        let caller = core::mem::replace(&mut self.env, scope(&func, args));
+        self.depth += 1;
+        self.tasks.push(Task::Report(expr));
+        self.tasks.push(Task::Return { caller, span });
+        self.tasks
+            .push(Task::Eval(func.body.clone().expect("checked by `callee`")));
+
+        Ok(None)
+    }
+
+    /// Schedules the evaluation of `expr`, which must be a number.
+    fn push_number(&mut self, expr: Expr) {
+        self.tasks.push(Task::ExpectNumber(expr.clone()));
+        self.tasks.push(Task::Eval(expr));
+    }
+
+    /// Pops a value that `Task::ExpectNumber` checked is a number.
+    fn pop_number(&mut self) -> f64 {
+        match self.values.pop() {
+            Some(Value::Number(value)) => value,
+            value => unreachable!("expected a number on the stack, found {value:?}"),
+        }
+    }
+
+    /// Restores the variable `name` to `shadowed`, removing it if `None`.
+    fn restore(&mut self, name: String, shadowed: Option<f64>) {
+        match shadowed {
+            Some(value) => self.env.insert(name, value),
+            None => self.env.remove(&name),
+        };
+    }
+
+    /// Abandons the evaluation being stepped through, leaving the scopes it
+    /// entered. Returns the span of the outermost call it was in, if any.
+    fn unwind(&mut self) -> Option<Span> {
+        let mut outermost = None;
+        while let Some(task) = self.tasks.pop() {
+            match task {
+                Task::Restore { name, shadowed } => self.restore(name, shadowed),
+                Task::Return { caller, span } => {
+                    self.env = caller;
+                    self.depth -= 1;
+                    outermost = Some(span);
+                },
+                _ => (),
+            }
+        }
+        self.values.clear();
+        outermost
+    }
+}
+
+/// A function that may be called, as found by `Interp::callee`.
+enum Callee {
+    Builtin(Builtin),
+    /// A function with a body.
+    Function(Rc<Function>),
+}
+
+/// Returns the scope of a call of `func` with `args`, holding only its
+/// parameters.
+fn scope(func: &Function, args: &[f64]) -> HashMap<String, f64> {
+    func.proto
+        .args
+        .iter()
+        .cloned()
+        .zip(args.iter().copied())
+        .collect()
+}
+
+/// Applies the binary operator `op` if it's a built-in one.
+fn builtin_binary(op: Operator, lhs: f64, rhs: f64) -> Option<f64> {
+    let value = match op.as_char() {
+        Some('+') => lhs + rhs,
+        Some('-') => lhs - rhs,
+        Some('*') => lhs * rhs,
+        Some('/') => lhs / rhs,
+        Some('%') => lhs % rhs,
+        Some('<') => f64::from(lhs < rhs),
+        Some('>') => f64::from(lhs > rhs),
+        Some('^') => lhs.powf(rhs),
+        None if op == Operator::pair('<', '=') => f64::from(lhs <= rhs),
+        None if op == Operator::pair('>', '=') => f64::from(lhs >= rhs),
+        None if op == Operator::pair('=', '=') => f64::from(lhs == rhs),
+        // Ordered, like the compiled `ONE` comparison: NaN is unequal to nothing.
+        None if op == Operator::pair('!', '=') => {
+            f64::from(lhs.partial_cmp(&rhs).is_some_and(Ordering::is_ne))
+        },
+        _ => return None,
+    };
+
+    Some(value)
+}
+
+fn not_a_number(expr: &Expr, value: &Value) -> Error {
+    Error::runtime(format!("Expected a number, but `{expr}` evaluates to `{value}`."))
+        .with_span(expr.span())
+}
+
+fn not_a_condition(expr: &Expr, value: &Value) -> Error {
+    Error::runtime(format!(
+        "Expected a number or a boolean, but `{expr}` evaluates to `{value}`."
+    ))
+    .with_span(expr.span())
+}
+
+fn check_arity(name: &str, arity: usize, args: &[f64]) -> Result<()> {
+    if arity == args.len() {
+        return Ok(());
+    }
+
+    Err(Error::runtime(format!(
+        "Function `{name}` takes {arity} arguments, but {} were given.",
+        args.len()
+    )))
+}
+
+// Both builtins flush as they go, so that a long-running loop shows its
+// progress instead of all of its output at the end.
+
+/// Prints its argument as an ASCII character.
+fn putchard(out: &mut dyn Write, args: &[f64]) -> f64 {
+    let _ = write!(out, "{}", args[0] as u8 as char);
+    let _ = out.flush();
+    args[0]
+}
+
+/// Prints its argument as a number, on its own line.
+fn printd(out: &mut dyn Write, args: &[f64]) -> f64 {
+    let _ = writeln!(out, "{}", args[0]);
+    let _ = out.flush();
+    args[0]
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::testing::{Buffer, parse_expr, parse_items};
+
+    fn eval(input: &str) -> Result<Value> { Interp::new().with_output(io::sink()).eval(&parse_expr(input)) }
+
+    #[test]
+    fn evaluates_remainder() {
+        assert_eq!(eval("7 % 3").unwrap(), 1.0);
+        assert_eq!(eval("-7 % 3").unwrap(), -1.0);
+    }
+
+    #[test]
+    fn evaluates_comparisons() {
+        assert_eq!(eval("3 > 2").unwrap(), 1.0);
+        assert_eq!(eval("2 >= 2").unwrap(), 1.0);
+        assert_eq!(eval("1 <= 0").unwrap(), 0.0);
+        // Like the compiled comparisons, these are ordered: nothing compares
+        // with NaN.
+        assert_eq!(eval("0/0 <= 1").unwrap(), 0.0);
+        assert_eq!(eval("0/0 >= 1").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn evaluates_do_blocks_to_their_last_value() {
+        assert_eq!(eval("do { printd(1); printd(2); 42 }").unwrap(), 42.0);
+        assert_eq!(eval("do {}").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn evaluates_equality() {
+        assert_eq!(eval("1 == 1").unwrap(), 1.0);
+        assert_eq!(eval("1 != 1").unwrap(), 0.0);
+        assert_eq!(eval("1 != 2").unwrap(), 1.0);
+        // Like the compiled comparisons, these are ordered: NaN equals nothing,
+        // but isn't unequal to anything either.
+        assert_eq!(eval("0/0 == 0/0").unwrap(), 0.0);
+        assert_eq!(eval("0/0 != 1").unwrap(), 0.0);
+    }
+
+    /// Defines every function of `input` and evaluates its top-level
+    /// expressions, returning the value of the last one.
+    fn run(input: &str) -> Result<Value> {
+        let results = Interp::new()
+            .with_output(io::sink())
+            .eval_program(&parse_items(input))?;
+        Ok(results.into_iter().flatten().last().unwrap_or(Value::Number(0.0)))
+    }
+
+    #[test]
+    fn calls_defined_functions() {
+        assert_eq!(run("def add(a, b) a+b; add(2,3)").unwrap(), 5.0);
+        assert_eq!(
+            run("def fib(n) if n < 2 then n else fib(n-1) + fib(n-2); fib(10)").unwrap(),
+            55.0
+        );
+    }
+
+    #[test]
+    fn reports_unknown_names_and_arity_mismatches() {
+        assert!(matches!(eval("x + 1"), Err(Error::Runtime { .. })));
+        assert!(matches!(eval("nope(1)"), Err(Error::Runtime { .. })));
+
+        let err = run("def add(a, b) a+b; add(2)").unwrap_err();
+        assert_eq!(
+            err.message(),
+            "Function `add` takes 2 arguments, but 1 were given."
+        );
+    }
+
+    #[test]
+    fn evaluates_loops_and_restores_shadowed_variables() {
+        // The body runs for i = 0, 1, 2, and the end is tested after it.
+        assert_eq!(
+            run("var n = 0 in do { for i = 0, i < 2 in n = n + 1; n }").unwrap(),
+            3.0
+        );
+        assert_eq!(run("var i = 7 in do { for i = 0, i < 3 in i; i }").unwrap(), 7.0);
+        assert_eq!(eval("for i = 0, i < 3 in i").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn limits_the_call_depth() {
+        let err = run("def f(x) f(x); f(1)").unwrap_err();
+        assert!(
+            matches!(&err, Error::Runtime { message, .. } if message.contains("maximum call depth")),
+            "{err}"
+        );
+
+        let source = "def count(n) if n < 1 then 0 else 1 + count(n - 1);";
+        assert_eq!(
+            run(&format!("{source} count({})", MAX_CALL_DEPTH - 1)).unwrap(),
+            (MAX_CALL_DEPTH - 1) as f64
+        );
+    }
+
+    /// Steps through the evaluation of `expr` to its end, returning each node
+    /// with its value, and the value of `expr`.
+    fn step_through(interp: &mut Interp, expr: &Expr) -> (Vec<String>, Result<Value>) {
+        interp.start_eval(expr);
+
+        let mut steps = vec![];
+        loop {
+            match interp.eval_step() {
+                StepResult::Evaluated { expr, value } => steps.push(format!("{expr} => {value}")),
+                StepResult::Done(value) => return (steps, Ok(value)),
+                StepResult::Failed(err) => return (steps, Err(err)),
+            }
+        }
+    }
+
+    #[test]
+    fn steps_through_operands_before_their_operators() {
+        let (steps, value) = step_through(&mut Interp::new(), &parse_expr("1 + 2 * 3"));
+        assert_eq!(steps, [
+            "1 => 1",
+            "2 => 2",
+            "3 => 3",
+            "(2 * 3) => 6",
+            "(1 + (2 * 3)) => 7"
+        ]);
+        assert_eq!(value.unwrap(), 7.0);
+    }
+
+    #[test]
+    fn steps_into_function_bodies() {
+        let mut interp = Interp::new();
+        interp.eval_program(&parse_items("def sq(x) x * x")).unwrap();
+
+        let (steps, value) = step_through(&mut interp, &parse_expr("sq(3) + 1"));
+        assert_eq!(steps, [
+            "3 => 3",
+            "x => 3",
+            "x => 3",
+            "(x * x) => 9",
+            "sq(3) => 9",
+            "1 => 1",
+            "(sq(3) + 1) => 10"
+        ]);
+        assert_eq!(value.unwrap(), 10.0);
+        assert!(matches!(interp.eval_step(), StepResult::Failed(_)));
+    }
+
+    #[test]
+    fn steps_to_the_same_results_as_eval() {
+        let source = "def count(n) if n < 1 then 0 else 1 + count(n - 1);
+                      def binary| 5 (a, b) if a then 1 else if b then 1 else 0;
+                      def bad(x) (x, x) + 1;";
+        for input in [
+            "var a = 1, b = a + 1 in a * b",
+            "var x in x = 3 : x + 1",
+            "for i = 0, i < 3 in printd(i)",
+            "var t = 0 in (for i = 1, i < 5, 2 in t = t + i) : t",
+            "do { printd(1); (1, (2, 3)) }",
+            "do {}",
+            "if 2 < 1 then 5 else (6, 7)",
+            "0 | 1",
+            "count(5)",
+            "count(1000)",
+            "1 + bad(2)",
+            "var x = (1, 2) in x",
+            "if (1, 2) then 1 else 0",
+            "y = 1",
+            "1 = 1",
+            "sq(1)",
+            "count(1, 2)",
+            "for i = 0, 1 in 0",
+        ] {
+            let program = parse_items(&format!("{source} {input}"));
+            let expr = program.last().unwrap().body.as_ref().unwrap();
+            let interp = || {
+                let mut interp = Interp::new().with_output(io::sink()).with_max_iterations(100);
+                interp.define_ahead(&program);
+                interp
+            };
+
+            let mut stepped = interp();
+            let (_, value) = step_through(&mut stepped, expr);
+            let expected = interp().eval(expr);
+            match (value, expected) {
+                (Ok(value), Ok(expected)) => assert_eq!(value, expected, "{input}"),
+                (Err(err), Err(expected)) => {
+                    assert_eq!(err.to_string(), expected.to_string(), "{input}");
+                },
+                (value, expected) => panic!("{input}: stepped to {value:?}, but evaluates to {expected:?}"),
+            }
+            assert!(
+                stepped.env.is_empty() && stepped.depth == 0,
+                "{input}: {stepped:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn limits_loop_iterations() {
+        let interp = || Interp::new().with_max_iterations(10);
+
+        let err = interp().eval(&parse_expr("1 + for i = 0, 1 in 0")).unwrap_err();
+        assert!(
+            matches!(&err, Error::Runtime { message, .. } if message.contains("iteration limit")),
+            "{err}"
+        );
+        assert_eq!(err.span(), Some(Span::new(4, 21)));
+        assert_eq!(interp().eval(&parse_expr("for i = 1, i < 10 in 0")).unwrap(), 0.0);
+
+        // The limit is on the iterations of every loop together: 4 outer and 16
+        // inner ones here.
+        let err = interp()
+            .eval(&parse_expr("for i = 1, i < 4 in for j = 1, j < 4 in 0"))
+            .unwrap_err();
+        assert!(err.message().contains("iteration limit"), "{err}");
+
+        // Each top-level evaluation starts counting over.
+        let mut interp = interp();
+        for _ in 0..3 {
+            assert_eq!(interp.eval(&parse_expr("for i = 1, i < 8 in 0")).unwrap(), 0.0);
+        }
+    }
+
+    #[test]
+    fn calls_builtins_before_functions() {
+        fn record(out: &mut dyn Write, args: &[f64]) -> f64 {
+            let _ = write!(out, "<{}>", args[0]);
+            args[0] * 2.0
+        }
+
+        let buffer = Buffer::default();
+        let mut interp = Interp::new().with_output(buffer.clone());
+        let mut eval = |input| interp.eval(&parse_expr(input));
+        assert_eq!(eval("putchard(65)").unwrap(), 65.0);
+        assert_eq!(buffer.contents(), "A");
+        assert_eq!(eval("printd(42)").unwrap(), 42.0);
+        assert_eq!(buffer.contents(), "A42\n");
+        assert!(matches!(eval("printd(1, 2)"), Err(Error::Runtime { .. })));
+
+        let buffer = Buffer::default();
+        let mut interp = Interp::new().with_output(buffer.clone());
+        interp.define_builtin("record", 1, record);
+        for func in parse_items("extern record(x); def record(x) x") {
+            interp.define(func);
+        }
+
+        let call = parse_expr("record(21)");
+        assert_eq!(interp.eval(&call).unwrap(), 42.0);
+        assert_eq!(buffer.contents(), "<21>");
+    }
+
+    #[test]
+    fn comparisons_evaluate_to_one_or_zero() {
+        assert_eq!(eval("3 < 4").unwrap(), 1.0);
+        assert_eq!(eval("5 > 9").unwrap(), 0.0);
+        assert_eq!(eval("4 < 3").unwrap(), 0.0);
+        assert_eq!(eval("9 > 5").unwrap(), 1.0);
+        // Ordered, like the compiled comparisons.
+        assert_eq!(eval("0/0 < 1").unwrap(), 0.0);
+        assert_eq!(eval("0/0 > 1").unwrap(), 0.0);
+    }
+
+    #[test]
+    fn sequences_return_their_last_value() {
+        assert_eq!(run("def f() printd(1) : printd(2) : 3; f()").unwrap(), 3.0);
+    }
+
+    #[test]
+    fn assigns_to_variables() {
+        assert_eq!(eval("var x = 1 in (x = x + 5)").unwrap(), 6.0);
+        assert_eq!(eval("var x = 1 in do { x = 7; x }").unwrap(), 7.0);
+
+        let err = eval("var x in (x + 1 = 2)").unwrap_err();
+        assert!(matches!(err, Error::Runtime { .. }), "{err}");
+        assert!(matches!(eval("y = 1"), Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn evaluates_powers() {
+        assert_eq!(eval("2 ^ 3 ^ 2").unwrap(), 512.0);
+        assert_eq!(eval("2 * 3 ^ 2").unwrap(), 18.0);
+        assert_eq!(eval("4 ^ 0.5").unwrap(), 2.0);
+    }
+
+    #[test]
+    fn flushes_builtin_output_as_it_goes() {
+        /// Records what had been written by each flush.
+        #[derive(Default)]
+        struct Flushes {
+            pending: Vec<u8>,
+            flushed: Rc<RefCell<Vec<String>>>,
+        }
+
+        impl Write for Flushes {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.pending.write(buf) }
+
+            fn flush(&mut self) -> io::Result<()> {
+                let pending = core::mem::take(&mut self.pending);
+                self.flushed
+                    .borrow_mut()
+                    .push(String::from_utf8(pending).unwrap());
+                Ok(())
+            }
+        }
+
+        let flushes = Flushes::default();
+        let flushed = Rc::clone(&flushes.flushed);
+        let expr = parse_expr("for i = 1, i < 3 in printd(i) : putchard(33)");
+        Interp::new().with_output(flushes).eval(&expr).unwrap();
+        assert_eq!(*flushed.borrow(), ["1\n", "!", "2\n", "!", "3\n", "!"]);
+    }
+
+    #[test]
+    fn can_leave_out_the_builtins() {
+        let expr = parse_expr("printd(1)");
+
+        let err = Interp::without_builtins().eval(&expr).unwrap_err();
+        assert!(
+            matches!(&err, Error::Runtime { message, .. } if message.contains("Unknown function")),
+            "{err}"
+        );
+        assert_eq!(Interp::new().with_output(io::sink()).eval(&expr).unwrap(), 1.0);
+    }
+
+    #[test]
+    fn lists_the_builtins() {
+        let mut names: Vec<_> = Interp::new().builtins().map(|proto| proto.to_string()).collect();
+        names.sort();
+        assert_eq!(names, ["printd(x0)", "putchard(x0)"]);
+        assert_eq!(Interp::without_builtins().builtins().count(), 0);
+    }
+
+    #[test]
+    fn evaluates_programs() {
+        let program = parse_items("sq(3); def sq(x) x*x; sq(4)");
+
+        let mut interp = Interp::new();
+        assert_eq!(interp.eval_program(&program).unwrap(), [
+            Some(Value::Number(9.0)),
+            None,
+            Some(Value::Number(16.0))
+        ]);
+
+        let program = parse_items("extern g(x); g(1); 2");
+        assert!(matches!(
+            interp.eval_program(&program),
+            Err(Error::Runtime { .. })
+        ));
+    }
+
+    #[test]
+    fn applies_redefinitions_in_program_order() {
+        let program = parse_items("def f() 1; f(); def f() 2; f()");
+
+        let mut interp = Interp::new();
+        assert_eq!(interp.eval_program(&program).unwrap(), [
+            None,
+            Some(Value::Number(1.0)),
+            None,
+            Some(Value::Number(2.0))
+        ]);
+
+        // A function defined by an earlier program is only replaced once its
+        // redefinition runs.
+        let program = parse_items("f(); def f() 3; f()");
+        assert_eq!(interp.eval_program(&program).unwrap(), [
+            Some(Value::Number(2.0)),
+            None,
+            Some(Value::Number(3.0))
+        ]);
+    }
+
+    #[test]
+    fn comparisons_evaluate_to_numbers() {
+        assert_eq!(eval("1 < 2").unwrap(), Value::Number(1.0));
+        assert_eq!(eval("if 2 < 1 then 5 else 6").unwrap(), Value::Number(6.0));
+        assert_eq!(eval("1.5 + 1").unwrap().to_string(), "2.5");
+        assert_eq!(
+            Value::Tuple(vec![1.0.into(), Value::Tuple(vec![2.0.into()])]).to_string(),
+            "(1, (2))"
+        );
+        assert_eq!(
+            Value::Tuple(vec![Value::Bool(true), Value::Unit]).to_string(),
+            "(true, ())"
+        );
+    }
+
+    #[test]
+    fn conditions_are_numbers_or_booleans() {
+        assert_eq!(Value::Number(2.0).is_true(), Some(true));
+        assert_eq!(Value::Number(0.0).is_true(), Some(false));
+        assert_eq!(Value::Bool(false).is_true(), Some(false));
+        assert_eq!(Value::Unit.is_true(), None);
+        assert_eq!(Value::Tuple(vec![]).is_true(), None);
+
+        let err = eval("if (1, 2) then 1 else 0").unwrap_err();
+        assert_eq!(
+            err.message(),
+            "Expected a number or a boolean, but `(1, 2)` evaluates to `(1, 2)`."
+        );
+        assert_eq!(err.span(), Some(Span::new(3, 9)));
+    }
+
+    #[test]
+    fn evaluates_tuples() {
+        assert_eq!(
+            eval("(1, 2)").unwrap(),
+            Value::Tuple(vec![1.0.into(), 2.0.into()])
+        );
+        assert_eq!(
+            run("def swap(a, b) (b, a); swap(1, 2)").unwrap().to_string(),
+            "(2, 1)"
+        );
+        assert_eq!(eval("((1, 2), 3)").unwrap().to_string(), "((1, 2), 3)");
+
+        // Everything but a tuple's elements and the values of functions must be a
+        // number.
+        for input in ["(1, 2) + 1", "if (1, 2) then 1 else 0", "var x = (1, 2) in x"] {
+            let err = eval(input).unwrap_err();
+            assert!(
+                matches!(&err, Error::Runtime { message, .. } if message.contains("Expected a number")),
+                "{err}"
+            );
+        }
+        assert!(matches!(eval("f(x = 1)"), Err(Error::Runtime { .. })));
+    }
+
+    #[test]
+    fn points_runtime_errors_at_the_failing_expression() {
+        assert_eq!(eval("1 + (2, 3)").unwrap_err().span(), Some(Span::new(4, 10)));
+        assert_eq!(eval("1 + y").unwrap_err().span(), Some(Span::new(4, 5)));
+        assert_eq!(
+            eval("var x in (y = 1)").unwrap_err().span(),
+            Some(Span::new(10, 11))
+        );
+
+        // Errors in a function's body point at the call, which is where the input
+        // being evaluated fails.
+        let err = run("def f(x) x + (1, 2);\n1 + f(1)").unwrap_err();
+        assert!(err.message().contains("Expected a number"), "{err}");
+        assert_eq!(err.span(), Some(Span::new(25, 29)));
+    }
+}
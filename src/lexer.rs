@@ -1,34 +1,117 @@
 //! The Kaleidoscope Lexer
 
-use core::iter::Peekable;
-use core::str::Chars;
+use core::ops::Range;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::io;
 
-use crate::token::Token;
+use crate::span::{Span, Spanned};
+use crate::token::{Keyword, Token};
+
+/// A user-supplied lexing rule. It is given the remaining input, starting at
+/// the next non-whitespace character, and may claim a prefix of it by
+/// returning the token along with the prefix length in bytes.
+pub type LexHook<'a> = Box<dyn FnMut(&str) -> Option<(Token, usize)> + 'a>;
 
 pub struct Lexer<'a> {
-    pos:   usize,
-    input: &'a str,
-    chars: Box<Peekable<Chars<'a>>>,
+    pos:            usize,
+    input:          Cow<'a, str>,
+    /// The number of line breaks skipped before the last token.
+    line_breaks:    usize,
+    /// The byte offset at which the last token starts.
+    token_start:    usize,
+    /// The 1-based line and column at which the last token starts.
+    token_position: (usize, usize),
+    /// The 1-based line of `pos`.
+    line:           usize,
+    /// The 1-based column of `pos`, in characters.
+    column:         usize,
+    hook:           Option<LexHook<'a>>,
+    /// Whether or not `token` returns comments, rather than skipping them.
+    comments:       bool,
 }
 
 impl<'a> Lexer<'a> {
-    /// Creates a new `Lexer`,
-    pub fn new(input: &'a str) -> Self {
-        Self {
+    /// Creates a new `Lexer`, skipping a leading `#!` interpreter line so
+    /// scripts can be run directly.
+    pub fn new(input: &'a str) -> Self { Self::from_cow(Cow::Borrowed(input)) }
+
+    fn from_cow(input: Cow<'a, str>) -> Self {
+        let mut lexer = Self {
             input,
-            chars: Box::new(input.chars().peekable()),
             pos: 0,
+            line_breaks: 0,
+            token_start: 0,
+            token_position: (1, 1),
+            line: 1,
+            column: 1,
+            hook: None,
+            comments: true,
+        };
+
+        if lexer.input.starts_with("#!") {
+            lexer.skip_line();
         }
+
+        lexer
+    }
+
+    /// Creates a new `Lexer` over the whole of `reader`, which must be valid
+    /// UTF-8. The input is read upfront and owned by the lexer.
+    pub fn from_reader(mut reader: impl io::Read) -> io::Result<Lexer<'static>> {
+        let mut input = String::new();
+        reader.read_to_string(&mut input)?;
+        Ok(Lexer::from_cow(Cow::Owned(input)))
+    }
+
+    /// Installs `hook` to run before the built-in rules for every token, e.g.
+    /// to try out new syntax without forking the lexer. When it returns `None`
+    /// or claims an empty prefix, the built-in rules apply.
+    #[must_use]
+    pub fn with_hook(mut self, hook: impl FnMut(&str) -> Option<(Token, usize)> + 'a) -> Self {
+        self.hook = Some(Box::new(hook));
+        self
+    }
+
+    /// Sets whether or not comments are returned as `Token::Comment`. When
+    /// they are skipped, `line_breaks` counts the line breaks around them too.
+    #[must_use]
+    pub const fn with_comments(mut self, comments: bool) -> Self {
+        self.comments = comments;
+        self
     }
 
     pub fn token(&mut self) -> io::Result<Token> {
+        self.line_breaks = 0;
+
+        loop {
+            match self.lex_token()? {
+                Token::Comment(_) if !self.comments => continue,
+                token => return Ok(token),
+            }
+        }
+    }
+
+    fn lex_token(&mut self) -> io::Result<Token> {
         self.skip_whitespace();
 
         let start = self.pos;
+        self.token_start = start;
+        self.token_position = (self.line, self.column);
+
+        if let Some(hook) = &mut self.hook
+            && start < self.input.len()
+            && let Some((token, len)) = hook(&self.input[start..])
+            && len > 0
+        {
+            while self.pos < start + len && self.peek().is_some() {
+                self.advance();
+            }
+            return Ok(token);
+        }
 
         // Check for end of file. Don't eat the EOF.
-        let &ch = match self.chars.peek() {
+        let ch = match self.peek() {
             Some(c) => c,
             None => return Ok(Token::EOF),
         };
@@ -38,68 +121,470 @@ impl<'a> Lexer<'a> {
         let token = match ch {
             '(' => Token::LParen,
             ')' => Token::RParen,
+            '{' => Token::LBrace,
+            '}' => Token::RBrace,
             ',' => Token::Comma,
-            '#' => self.lex_comment(),
-            '.' | '0'..='9' => self.lex_number(start),
+            '"' => self.lex_string(start)?,
+            '\'' => self.lex_char()?,
+            '#' if self.peek() == Some('{') => self.lex_block_comment(start)?,
+            '#' => self.lex_comment(start),
+            '0' if matches!(self.peek(), Some('x' | 'X')) => self.lex_hex_float()?,
+            '0' if matches!(self.peek(), Some('o' | 'O' | 'b' | 'B')) => self.lex_radix_int()?,
+            '.' | '0'..='9' => self.lex_number(start)?,
             'a'..='z' | 'A'..='Z' | '_' => self.lex_ident(start),
+            // Comparison and equality operators.
+            op @ ('<' | '>' | '=' | '!') if self.peek() == Some('=') => {
+                self.advance();
+                Token::Op2([op, '='])
+            },
             op => Token::Op(op),
         };
 
         Ok(token)
     }
 
+    /// Returns the number of line breaks in the whitespace skipped before the
+    /// last token returned by `token`. A comment does not include its
+    /// terminating line break, so a comment directly above a token is
+    /// separated from it by a single line break.
+    pub const fn line_breaks(&self) -> usize { self.line_breaks }
+
+    /// Returns the 1-based line and column of the next character to be lexed.
+    /// Columns count characters, and `\r\n` counts as a single line break.
+    pub const fn position(&self) -> (usize, usize) { (self.line, self.column) }
+
+    /// Returns the 1-based line and column of the start of the last token
+    /// returned by `token`.
+    pub const fn token_position(&self) -> (usize, usize) { self.token_position }
+
+    /// Consumes the lexer, returning an iterator that yields every `Token` up
+    /// to EOF. Unlike the `Iterator` impl, a lexing error is reported as the
+    /// final item instead of silently ending the stream.
+    pub fn results(mut self) -> impl Iterator<Item = io::Result<Token>> + 'a {
+        let mut done = false;
+
+        core::iter::from_fn(move || {
+            if done {
+                return None;
+            }
+
+            match self.token() {
+                Ok(Token::EOF) => {
+                    done = true;
+                    None
+                },
+                Ok(token) => Some(Ok(token)),
+                Err(err) => {
+                    done = true;
+                    Some(Err(err))
+                },
+            }
+        })
+    }
+
+    /// Returns the span of the last token returned by `token`. After an
+    /// error, it spans what was read of the token that failed to lex.
+    pub const fn token_span(&self) -> Span { Span::new(self.token_start, self.pos) }
+
+    /// Lexes the next `Token` along with its span in the input.
+    pub fn spanned_token(&mut self) -> io::Result<Spanned<Token>> {
+        let value = self.token()?;
+
+        Ok(Spanned {
+            value,
+            span: self.token_span(),
+        })
+    }
+
+    /// Consumes the lexer, returning every `Token` up to EOF along with its
+    /// span, or the first lexing error.
+    pub fn spanned(mut self) -> io::Result<Vec<Spanned<Token>>> {
+        let mut tokens = vec![];
+
+        loop {
+            let token = self.spanned_token()?;
+            if token.value == Token::EOF {
+                return Ok(tokens);
+            }
+            tokens.push(token);
+        }
+    }
+
+    /// Returns the next character to be lexed, without consuming it.
+    #[inline]
+    fn peek(&self) -> Option<char> { self.input[self.pos..].chars().next() }
+
     #[inline]
     fn advance(&mut self) {
-        self.chars.next();
-        self.pos += 1;
+        // `pos` is a byte offset into `input`, so step over the whole char.
+        if let Some(ch) = self.peek() {
+            self.pos += ch.len_utf8();
+
+            // The '\r' of a "\r\n" is followed by a reset of the column anyway.
+            if ch == '\n' {
+                self.line += 1;
+                self.column = 1;
+            } else {
+                self.column += 1;
+            }
+        }
     }
 
     fn skip_whitespace(&mut self) {
-        while let Some(&ch) = self.chars.peek() {
+        while let Some(ch) = self.peek() {
             if !ch.is_whitespace() {
                 break;
             }
+            if ch == '\n' {
+                self.line_breaks += 1;
+            }
             self.advance();
         }
     }
 
-    fn lex_comment(&mut self) -> Token {
-        while let Some(&ch) = self.chars.peek() {
-            self.advance();
+    /// Advances up to, but not past, the next line break.
+    fn skip_line(&mut self) {
+        while let Some(ch) = self.peek() {
             if ch == '\n' || ch == '\r' {
                 break;
             }
+            self.advance();
         }
-        Token::Comment
     }
 
-    fn lex_number(&mut self, start: usize) -> Token {
-        while let Some(&ch) = self.chars.peek() {
-            if ch != '.' && !ch.is_ascii_hexdigit() {
+    fn lex_comment(&mut self, start: usize) -> Token {
+        self.skip_line();
+
+        // Strip the leading '#'.
+        Token::Comment(self.input[start + 1..self.pos].to_string())
+    }
+
+    /// Lexes a `#{ ... #}` block comment, which may span several lines and
+    /// contain nested block comments. The leading `#` has already been
+    /// consumed.
+    fn lex_block_comment(&mut self, start: usize) -> io::Result<Token> {
+        // Eat the '{'.
+        self.advance();
+
+        let mut depth = 1;
+
+        while let Some(ch) = self.peek() {
+            self.advance();
+
+            match (ch, self.peek()) {
+                ('#', Some('{')) => depth += 1,
+                ('#', Some('}')) => depth -= 1,
+                _ => continue,
+            }
+
+            self.advance();
+
+            if depth == 0 {
+                // Strip the delimiters.
+                return Ok(Token::Comment(self.input[start + 2..self.pos - 2].to_string()));
+            }
+        }
+
+        Err(self.log_err("Unterminated block comment."))
+    }
+
+    fn lex_string(&mut self, start: usize) -> io::Result<Token> {
+        while let Some(ch) = self.peek() {
+            self.advance();
+            if ch == '"' {
+                // Strip the surrounding quotes.
+                let slice = &self.input[start + 1..self.pos - 1];
+                return Ok(Token::Str(slice.to_string()));
+            }
+        }
+
+        Err(self.log_err("Unterminated string literal."))
+    }
+
+    /// Lexes a character literal such as `'A'` or `'\n'` into the number of its
+    /// code point, e.g. for `putchard`. The opening quote has already been
+    /// consumed.
+    fn lex_char(&mut self) -> io::Result<Token> {
+        const UNTERMINATED: &str = "Unterminated character literal.";
+
+        let ch = match self.peek() {
+            Some('\\') => {
+                self.advance();
+                match self.peek() {
+                    Some('n') => '\n',
+                    Some('t') => '\t',
+                    Some('\\') => '\\',
+                    Some('\'') => '\'',
+                    Some(_) => return Err(self.log_err("Unknown escape sequence in character literal.")),
+                    None => return Err(self.log_err(UNTERMINATED)),
+                }
+            },
+            Some('\'') => return Err(self.log_err("Empty character literal.")),
+            Some(ch) => ch,
+            None => return Err(self.log_err(UNTERMINATED)),
+        };
+
+        self.advance();
+
+        match self.peek() {
+            Some('\'') => {
+                self.advance();
+                Ok(Token::Number(f64::from(u32::from(ch))))
+            },
+            Some(_) => Err(self.log_err("Expected a single character in character literal.")),
+            None => Err(self.log_err(UNTERMINATED)),
+        }
+    }
+
+    /// Lexes a hexadecimal literal: either an integer such as `0xFF`, or a
+    /// C99-style float such as `0x1.8p3`, i.e. a hex mantissa with an optional
+    /// fraction, scaled by a binary exponent. As in C99, the exponent is
+    /// mandatory once there is a fraction. The leading `0` has already been
+    /// consumed.
+    fn lex_hex_float(&mut self) -> io::Result<Token> {
+        // Eat the 'x'.
+        self.advance();
+
+        let mut mantissa = 0.0;
+        let mut scale = 1.0;
+        let mut digits = 0;
+        let mut seen_dot = false;
+
+        while let Some(ch) = self.peek() {
+            if let Some(digit) = ch.to_digit(16) {
+                if seen_dot {
+                    scale /= 16.0;
+                    mantissa += f64::from(digit) * scale;
+                } else {
+                    mantissa = mantissa * 16.0 + f64::from(digit);
+                }
+                digits += 1;
+            } else if ch == '.' && !seen_dot {
+                seen_dot = true;
+            } else {
                 break;
             }
             self.advance();
         }
 
+        if digits == 0 {
+            return Err(self.log_err("Expected hexadecimal digits after '0x'."));
+        }
+
+        match self.peek() {
+            Some('p' | 'P') => self.advance(),
+            _ if !seen_dot => return Ok(Token::Number(mantissa)),
+            _ => return Err(self.log_err("Expected 'p' exponent in hexadecimal float literal.")),
+        }
+
+        let exp_start = self.pos;
+        if let Some('+' | '-') = self.peek() {
+            self.advance();
+        }
+        while let Some(ch) = self.peek()
+            && ch.is_ascii_digit()
+        {
+            self.advance();
+        }
+
+        let exponent: i32 = self.input[exp_start..self.pos]
+            .parse()
+            .map_err(|_| self.log_err("Malformed exponent in hexadecimal float literal."))?;
+
+        Ok(Token::Number(mantissa * 2f64.powi(exponent)))
+    }
+
+    /// Lexes an octal (`0o17`) or binary (`0b1010`) integer literal. The
+    /// leading `0` has already been consumed.
+    fn lex_radix_int(&mut self) -> io::Result<Token> {
+        let (radix, name) = match self.peek() {
+            Some('b' | 'B') => (2, "binary"),
+            _ => (8, "octal"),
+        };
+
+        // Eat the 'b' or 'o'.
+        self.advance();
+
+        let mut value = 0.0;
+        let mut digits = 0;
+
+        while let Some(ch) = self.peek()
+            && ch.is_ascii_alphanumeric()
+        {
+            let digit = ch
+                .to_digit(radix)
+                .ok_or_else(|| self.log_err(&format!("Invalid digit '{ch}' in {name} literal.")))?;
+
+            value = value * f64::from(radix) + f64::from(digit);
+            digits += 1;
+            self.advance();
+        }
+
+        if digits == 0 {
+            return Err(self.log_err(&format!("Expected digits in {name} literal.")));
+        }
+
+        Ok(Token::Number(value))
+    }
+
+    /// Lexes a decimal number literal, whose first character has already been
+    /// consumed. A literal is a run of decimal digits with at most one `.`,
+    /// optionally followed by an exponent, and it ends at the first character
+    /// that can't extend it:
+    ///
+    /// - `12`, `1.5`, `.5` and `2.5e-3` are numbers;
+    /// - `2x` is the number `2` followed by the identifier `x`;
+    /// - a `.` must be followed by a digit, so `1.` and `2.x` are errors;
+    /// - a second `.` is an error, so `2.0.5` doesn't silently split;
+    /// - an `e` or `E` starts an exponent, which must have digits after its
+    ///   optional sign, so `1e` and `1e+` are errors.
+    fn lex_number(&mut self, start: usize) -> io::Result<Token> {
+        let mut seen_dot = self.input[start..].starts_with('.');
+        let mut needs_digit = seen_dot;
+
+        while let Some(ch) = self.peek() {
+            match ch {
+                '0'..='9' => needs_digit = false,
+                '.' if seen_dot => return Err(self.log_err("Unexpected second '.' in number literal.")),
+                '.' => {
+                    seen_dot = true;
+                    needs_digit = true;
+                },
+                _ => break,
+            }
+            self.advance();
+        }
+
+        if needs_digit {
+            return Err(self.log_err("Expected a digit after '.' in number literal."));
+        }
+
+        if let Some('e' | 'E') = self.peek() {
+            self.advance();
+
+            if let Some('+' | '-') = self.peek() {
+                self.advance();
+            }
+
+            let digits_start = self.pos;
+            while let Some(ch) = self.peek()
+                && ch.is_ascii_digit()
+            {
+                self.advance();
+            }
+
+            if self.pos == digits_start {
+                return Err(self.log_err("Expected digits in the exponent of number literal."));
+            }
+        }
+
         let slice = &self.input[start..self.pos];
-        Token::Number(slice.parse().unwrap_or_default())
+        slice
+            .parse()
+            .map(Token::Number)
+            .map_err(|_| self.log_err("Malformed number literal."))
     }
 
     fn lex_ident(&mut self, start: usize) -> Token {
-        while let Some(&ch) = self.chars.peek() {
+        while let Some(ch) = self.peek() {
             if ch != '_' && !ch.is_alphanumeric() {
                 break;
             }
             self.advance();
         }
 
-        match &self.input[start..self.pos] {
-            "def" => Token::Def,
-            "extern" => Token::Extern,
-            "binary" => Token::Binary,
-            ident => Token::Ident(ident.to_string()),
+        let ident = &self.input[start..self.pos];
+        match Keyword::from_ident(ident) {
+            Some(keyword) => Token::Keyword(keyword),
+            None => Token::Ident(ident.to_string()),
+        }
+    }
+
+    fn log_err(&self, error: &str) -> io::Error { io::Error::new(io::ErrorKind::InvalidData, error) }
+}
+
+/// Summary statistics about a source text, as computed by `stats`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct SourceStats {
+    pub lines:    usize,
+    /// The total number of tokens, comments included.
+    pub tokens:   usize,
+    /// The number of tokens of each kind, keyed by `Token::kind`.
+    pub by_kind:  BTreeMap<&'static str, usize>,
+    pub comments: usize,
+    pub defs:     usize,
+    pub externs:  usize,
+}
+
+/// Lexes the whole of `input`, returning every `Token` up to, but not
+/// including, EOF, or the first lexing error.
+pub fn tokenize(input: &str) -> io::Result<Vec<Token>> { Lexer::new(input).results().collect() }
+
+/// Computes statistics about `src` with a single lexing pass. Definitions and
+/// externs are counted by their keywords, without parsing them, so malformed
+/// items still count.
+pub fn stats(src: &str) -> io::Result<SourceStats> {
+    let mut stats = SourceStats {
+        lines: src.lines().count(),
+        ..SourceStats::default()
+    };
+
+    for token in Lexer::new(src).results() {
+        let token = token?;
+
+        stats.tokens += 1;
+        *stats.by_kind.entry(token.kind()).or_default() += 1;
+
+        match token {
+            Token::Comment(_) => stats.comments += 1,
+            Token::Keyword(Keyword::Def) => stats.defs += 1,
+            Token::Keyword(Keyword::Extern) => stats.externs += 1,
+            _ => (),
         }
     }
+
+    Ok(stats)
+}
+
+/// The tokens that changed between two versions of a source: `old[delta.old]`
+/// was replaced by `new[delta.new]`, and every token around them is the same
+/// in both.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct TokenDelta {
+    pub old: Range<usize>,
+    pub new: Range<usize>,
+}
+
+impl TokenDelta {
+    /// Returns a value indicating whether or not both versions have the same
+    /// tokens.
+    pub fn is_empty(&self) -> bool { self.old.is_empty() && self.new.is_empty() }
+}
+
+/// Computes the smallest single range of tokens that changed between `old`
+/// and `new`, covering insertions, deletions and replacements alike. Tokens are
+/// compared by value only, since an edit shifts the spans of everything after
+/// it.
+pub fn diff_tokens(old: &[Spanned<Token>], new: &[Spanned<Token>]) -> TokenDelta {
+    let prefix = old
+        .iter()
+        .zip(new)
+        .take_while(|(a, b)| a.value == b.value)
+        .count();
+
+    // Don't let the common suffix overlap the common prefix, e.g. when a token
+    // is inserted into a run of equal tokens.
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a.value == b.value)
+        .count();
+
+    TokenDelta {
+        old: prefix..old.len() - suffix,
+        new: prefix..new.len() - suffix,
+    }
 }
 
 impl Iterator for Lexer<'_> {
@@ -114,3 +599,252 @@ impl Iterator for Lexer<'_> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn results_end_after_the_first_error() {
+        let results: Vec<_> = Lexer::new("x + 2.0.5 y").results().collect();
+        assert!(matches!(results.as_slice(), [
+            Ok(Token::Ident(_)),
+            Ok(Token::Op('+')),
+            Err(_)
+        ]));
+    }
+
+    #[test]
+    fn skips_shebang_on_first_line_only() {
+        let tokens = tokenize("#!/usr/bin/env kaleidoscope\nx").unwrap();
+        assert_eq!(tokens, [Token::Ident("x".to_string())]);
+
+        let tokens = tokenize("x\n#!/usr/bin/env kaleidoscope").unwrap();
+        assert!(matches!(tokens.as_slice(), [Token::Ident(_), Token::Comment(_)]));
+    }
+
+    #[test]
+    fn lexes_hex_floats() {
+        assert_eq!(tokenize("0x1.8p3").unwrap(), [Token::Number(12.0)]);
+        assert_eq!(tokenize("0x1p-1").unwrap(), [Token::Number(0.5)]);
+        assert!(tokenize("0x1.8").is_err());
+    }
+
+    #[test]
+    fn hook_lexes_custom_tokens() {
+        let sigil = |rest: &str| {
+            let name = rest.strip_prefix('$')?;
+            let len = name
+                .find(|ch: char| !ch.is_ascii_alphanumeric())
+                .unwrap_or(name.len());
+            Some((Token::Str(name[..len].to_string()), len + 1))
+        };
+
+        let tokens = Lexer::new("$x + y")
+            .with_hook(sigil)
+            .results()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(tokens, [
+            Token::Str("x".to_string()),
+            Token::Op('+'),
+            Token::Ident("y".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn splits_numbers_deterministically() {
+        assert_eq!(tokenize("2x").unwrap(), [
+            Token::Number(2.0),
+            Token::Ident("x".to_string()),
+        ]);
+        assert!(tokenize("2.x").is_err());
+        assert!(tokenize("2.0.5").is_err());
+    }
+
+    #[test]
+    fn diffs_a_single_token_edit() {
+        let old = Lexer::new("def f(x) x + 1").spanned().unwrap();
+        let new = Lexer::new("def f(x) x + 42").spanned().unwrap();
+
+        assert_eq!(diff_tokens(&old, &new), TokenDelta { old: 7..8, new: 7..8 });
+    }
+
+    #[test]
+    fn diffs_insertions_into_runs_of_equal_tokens() {
+        let old = Lexer::new("a a").spanned().unwrap();
+        let new = Lexer::new("a a a").spanned().unwrap();
+
+        assert_eq!(diff_tokens(&old, &new), TokenDelta { old: 2..2, new: 2..3 });
+        assert_eq!(diff_tokens(&new, &old), TokenDelta { old: 2..3, new: 2..2 });
+    }
+
+    #[test]
+    fn unchanged_input_has_an_empty_diff() {
+        let old = Lexer::new("def f(x) x").spanned().unwrap();
+        let new = Lexer::new("def  f(x)\n  x").spanned().unwrap();
+
+        assert!(diff_tokens(&old, &new).is_empty());
+    }
+
+    #[test]
+    fn counts_source_statistics() {
+        let stats = stats("# Math.\nextern sin(x);\ndef f(x) x + 1\ndef g(y) f(y)\n").unwrap();
+
+        assert_eq!(stats.lines, 4);
+        assert_eq!(stats.tokens, 24);
+        assert_eq!(stats.comments, 1);
+        assert_eq!(stats.defs, 2);
+        assert_eq!(stats.externs, 1);
+        assert_eq!(stats.by_kind["keyword"], 3);
+        assert_eq!(stats.by_kind["identifier"], 9);
+        assert_eq!(stats.by_kind["parenthesis"], 8);
+    }
+
+    #[test]
+    fn tracks_lines_and_columns() {
+        let mut lexer = Lexer::new("def f(x)\r\n  x +\n\ty");
+        let mut positions = vec![];
+        loop {
+            let token = lexer.token().unwrap();
+            if token == Token::EOF {
+                break;
+            }
+            positions.push((token, lexer.token_position()));
+        }
+
+        assert_eq!(positions[1], (Token::Ident("f".to_string()), (1, 5)));
+        assert_eq!(positions[5], (Token::Ident("x".to_string()), (2, 3)));
+        assert_eq!(positions[6], (Token::Op('+'), (2, 5)));
+        assert_eq!(positions[7], (Token::Ident("y".to_string()), (3, 2)));
+        assert_eq!(lexer.position(), (3, 3));
+    }
+
+    #[test]
+    fn lexes_decimal_numbers_strictly() {
+        assert_eq!(tokenize(".5").unwrap(), [Token::Number(0.5)]);
+        assert_eq!(tokenize("12").unwrap(), [Token::Number(12.0)]);
+        assert!(tokenize("1.2.3").is_err());
+        assert!(tokenize("1.").is_err());
+    }
+
+    #[test]
+    fn lexes_exponents() {
+        assert_eq!(tokenize("1e10").unwrap(), [Token::Number(1e10)]);
+        assert_eq!(tokenize("2.5e-3").unwrap(), [Token::Number(2.5e-3)]);
+        assert_eq!(tokenize("6.022E23").unwrap(), [Token::Number(6.022e23)]);
+        assert_eq!(tokenize("1e+2").unwrap(), [Token::Number(100.0)]);
+        assert!(tokenize("1e").is_err());
+        assert!(tokenize("1e+").is_err());
+    }
+
+    #[test]
+    fn lexes_prefixed_integers() {
+        assert_eq!(tokenize("0xFF").unwrap(), [Token::Number(255.0)]);
+        assert_eq!(tokenize("0o17").unwrap(), [Token::Number(15.0)]);
+        assert_eq!(tokenize("0b1010").unwrap(), [Token::Number(10.0)]);
+        for input in ["0x", "0o", "0b"] {
+            assert!(tokenize(input).is_err(), "{input}");
+        }
+    }
+
+    #[test]
+    fn lexes_two_char_operators() {
+        let ident = |name: &str| Token::Ident(name.to_string());
+
+        assert_eq!(tokenize("a <= b").unwrap(), [
+            ident("a"),
+            Token::Op2(['<', '=']),
+            ident("b")
+        ]);
+        assert_eq!(tokenize("x != y").unwrap(), [
+            ident("x"),
+            Token::Op2(['!', '=']),
+            ident("y")
+        ]);
+        assert_eq!(tokenize("1 == 1").unwrap(), [
+            Token::Number(1.0),
+            Token::Op2(['=', '=']),
+            Token::Number(1.0),
+        ]);
+        // `=` alone followed by something else stays a single-char operator.
+        assert_eq!(tokenize("a = b").unwrap(), [
+            ident("a"),
+            Token::Op('='),
+            ident("b")
+        ]);
+    }
+
+    #[test]
+    fn lexes_block_comments() {
+        let ident = |name: &str| Token::Ident(name.to_string());
+
+        assert_eq!(tokenize("a #{ b\nb #} c").unwrap(), [
+            ident("a"),
+            Token::Comment(" b\nb ".to_string()),
+            ident("c"),
+        ]);
+        assert_eq!(tokenize("a #{ x #{ y #} z #} c").unwrap(), [
+            ident("a"),
+            Token::Comment(" x #{ y #} z ".to_string()),
+            ident("c"),
+        ]);
+        assert!(tokenize("a #{ x #{ y #} c").is_err());
+    }
+
+    #[test]
+    fn lexes_from_a_reader() {
+        let reader = io::Cursor::new(b"def f(x) x".to_vec());
+        let tokens = Lexer::from_reader(reader)
+            .unwrap()
+            .results()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(tokens, tokenize("def f(x) x").unwrap());
+
+        assert!(Lexer::from_reader(io::Cursor::new(vec![0xff])).is_err());
+    }
+
+    #[test]
+    fn tokenizes_without_the_eof_sentinel() {
+        assert_eq!(tokenize("").unwrap(), []);
+        assert_eq!(tokenize("  \n\t").unwrap(), []);
+        assert_eq!(tokenize("# only a comment").unwrap(), [Token::Comment(
+            " only a comment".to_string()
+        )]);
+        assert_eq!(tokenize("f(1, x)").unwrap(), [
+            Token::Ident("f".to_string()),
+            Token::LParen,
+            Token::Number(1.0),
+            Token::Comma,
+            Token::Ident("x".to_string()),
+            Token::RParen,
+        ]);
+    }
+
+    #[test]
+    fn comments_can_be_skipped() {
+        let tokens = Lexer::new("1 + # hi\n 2")
+            .with_comments(false)
+            .results()
+            .collect::<io::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(tokens, [Token::Number(1.0), Token::Op('+'), Token::Number(2.0)]);
+    }
+
+    #[test]
+    fn lexes_character_literals_as_numbers() {
+        for (input, value) in [
+            (r"'A'", 65.0),
+            (r"'\n'", 10.0),
+            (r"'\t'", 9.0),
+            (r"'\\'", 92.0),
+            (r"'\''", 39.0),
+        ] {
+            assert_eq!(tokenize(input).unwrap(), [Token::Number(value)], "{input}");
+        }
+        for input in ["'ab'", "'A", "''", r"'\q'"] {
+            assert!(tokenize(input).is_err(), "{input}");
+        }
+    }
+}
@@ -1,4 +1,17 @@
+pub mod analysis;
+pub mod arena;
 pub mod ast;
+#[cfg(feature = "llvm")] pub mod codegen;
+pub mod diagnostics;
+pub mod docs;
+pub mod error;
+pub mod interp;
 pub mod lexer;
+pub mod operator;
+pub mod optimize;
 pub mod parser;
+pub mod pretty;
+pub mod span;
+#[cfg(feature = "testing")] pub mod testing;
 pub mod token;
+pub mod visit;
@@ -1,11 +1,23 @@
 #![allow(unused)]
-use std::collections::HashMap;
-use std::io;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 use clap::Parser as _;
-use kaleidoscope::lexer::Lexer;
+#[cfg(feature = "llvm")]
+use inkwell::context::Context;
+#[cfg(feature = "llvm")]
+use inkwell::values::{AnyValue as _, FunctionValue};
+use kaleidoscope::ast::{Function, Prototype};
+#[cfg(feature = "llvm")]
+use kaleidoscope::codegen::Compiler;
+use kaleidoscope::error::Error;
+use kaleidoscope::interp::{Interp, Value};
+use kaleidoscope::lexer::{Lexer, tokenize};
 use kaleidoscope::parser::Parser;
 use kaleidoscope::token::Token;
+use kaleidoscope::{analysis, diagnostics};
 
 // ======================================================================================
 // PROGRAM ==============================================================================
@@ -21,12 +33,21 @@ macro_rules! print_flush {
     };
 }
 
+// macro used to print a line to the output of a session
+macro_rules! println_to {
+    ( $session:expr, $( $x:expr ),* ) => {
+        writeln!($session.out, $($x, )*).expect("Could not write to the output.")
+    };
+}
+
+#[cfg(feature = "llvm")]
 #[unsafe(no_mangle)]
 pub extern "C" fn putchard(x: f64) -> f64 {
     print_flush!("{}", x as u8 as char);
     x
 }
 
+#[cfg(feature = "llvm")]
 #[unsafe(no_mangle)]
 pub extern "C" fn printd(x: f64) -> f64 {
     println!("{x}");
@@ -35,133 +56,114 @@ pub extern "C" fn printd(x: f64) -> f64 {
 
 // Adding the functions above to a global array,
 // so Rust compiler won't remove them.
+#[cfg(feature = "llvm")]
 #[used]
 static EXTERNAL_FNS: [extern "C" fn(f64) -> f64; 2] = [putchard, printd];
 
 #[derive(Debug, clap::Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
+    /// Prints the tokens of each input before parsing it.
     #[arg(long = "dl")]
     display_lexer_output: bool,
 
+    /// Prints each parsed function or top-level expression.
     #[arg(long = "dp")]
     display_parser_output: bool,
 
+    /// Prints the tree of each parsed function body or top-level expression.
+    #[arg(long = "ast")]
+    display_ast: bool,
+
+    /// Prints the IR each function compiles to, after optimizations.
     #[arg(long = "dc")]
     display_compiler_output: bool,
 
+    /// Sets the optimization level of compiled code; 0 disables optimizations.
+    #[arg(short = 'O', default_value_t = 1)]
+    opt_level: u8,
+
+    /// Evaluates top-level expressions with the interpreter instead of the
+    /// JIT. This is the default when built without LLVM.
+    #[arg(long = "interp")]
+    interp: bool,
+
+    /// Runs the given input and exits.
     #[arg(short = 'e')]
     eval: Option<String>,
+
+    /// Starts the interactive prompt after running `-e`, keeping the state it
+    /// sets up.
+    #[arg(long = "interactive-after", requires = "eval")]
+    interactive_after: bool,
+
+    /// Runs a file before starting the interactive prompt, keeping the state
+    /// it sets up.
+    #[arg(long = "repl-script")]
+    repl_script: Option<PathBuf>,
+
+    /// Runs a file and exits, without starting the interactive prompt. The
+    /// value of its last top-level expression becomes the exit status.
+    #[arg(long = "file", conflicts_with_all = ["eval", "repl_script"])]
+    file: Option<PathBuf>,
+
+    /// Leaves out the interpreter's builtins `putchard` and `printd`, so that
+    /// interpreted programs calling them fail.
+    #[arg(long = "no-builtins")]
+    no_builtins: bool,
 }
 
 /// Entry point of the program; acts as a REPL.
-fn main() -> io::Result<()> {
-    // let Args {
-    //     display_lexer_output,
-    //     display_parser_output,
-    //     display_compiler_output,
-    //     eval,
-    //     ..
-    // } = Args::parse();
-
-    // let mut compute = |input: String| {
-    //     let precendence = [('=', 2), ('<', 10), ('+', 20), ('-', 20), ('*', 40),
-    // ('/', 40)];     let mut prec = HashMap::from_iter(precendence);
-
-    //     // Parse and (optionally) display input
-    //     if display_lexer_output {
-    //         println!(
-    //             "-> Attempting to parse lexed input: \n{:?}\n",
-    //             Lexer::new(&input).collect::<Vec<Token>>()
-    //         );
-    //     }
-
-    //     let (function, is_anonymous) = match Parser::new(input, &mut
-    // prec).parse() {         Ok(func) => {
-    //             let is_anon = func.is_anon;
-
-    //             if display_parser_output {
-    //                 if is_anon {
-    //                     println!("-> Expression parsed: \n{:?}\n", func.body);
-    //                 } else {
-    //                     println!("-> Function parsed: \n{func:?}\n");
-    //                 }
-    //             }
-
-    //             (function, is_anon)
-    //         },
-    //         Err(err) => {
-    //             println!("!> Error parsing expression: {err}");
-    //             return;
-    //         },
-    //     };
-
-    //     if display_compiler_output {
-    //         println!("-> Expression compiled to IR:");
-    //         function.print_to_stderr();
-    //     }
-    // };
-    // let stdin = std::io::stdin();
-    // let lexer = Lexer::new(stdin.lock().into());
-    // let mut parser = Parser::new(lexer)?;
-
-    // // Prime the first token.
-    // eprint!("ready> ");
-    // parser.get_next_token()?;
-
-    // // Run the main "interpreter loop" now.
-    // run_main_loop();
-    // if let Some(input) = eval {
-    //     compute(format!("{input}\n"));
-    // } else {
-    //     loop {
-    //         println!();
-    //         print_flush!("?> ");
-
-    //         let mut buffer = String::new();
-    //         io::stdin()
-    //             .read_line(&mut buffer)
-    //             .expect("Could not read from standard input.");
-
-    //         if buffer.starts_with("exit") || buffer.starts_with("quit") {
-    //             break;
-    //         } else if buffer.chars().all(char::is_whitespace) {
-    //             continue;
-    //         }
-
-    //         compute(buffer);
-    //     }
-    // }
+fn main() -> io::Result<ExitCode> {
+    let args = Args::parse();
+    let mut session = Session::new(&args);
 
-    loop {
-        println!();
-        print_flush!("?> ");
+    if let Some(path) = &args.file {
+        return Ok(run_file(path, &args, &mut session).into());
+    }
 
-        let mut input = String::new();
-        io::stdin()
-            .read_line(&mut input)
-            .expect("Could not read from standard input.");
+    start(&args, io::stdin().lock(), &mut session)
+}
 
-        if input.starts_with("exit") || input.starts_with("quit") {
-            break Ok(());
-        } else if input.chars().all(char::is_whitespace) {
-            continue;
+/// Runs the `--repl-script` and `-e`, then starts the prompt, reading lines
+/// from `input` until `exit`, `quit` or its end. With `-e`, the prompt only
+/// starts if `--interactive-after` is given; otherwise the program fails if
+/// `-e` does.
+fn start(args: &Args, mut input: impl BufRead, session: &mut Session) -> io::Result<ExitCode> {
+    if let Some(path) = &args.repl_script {
+        match std::fs::read_to_string(path) {
+            Ok(script) => {
+                run(&script, args, session);
+            },
+            Err(e) => eprintln!("Error reading {}: {e}", path.display()),
         }
+    }
 
-        let precendence = [('=', 2), ('<', 10), ('+', 20), ('-', 20), ('*', 40), ('/', 40)];
-        let mut prec = HashMap::from_iter(precendence);
-        let mut parser = Parser::new(&input, &mut prec);
+    if let Some(eval) = &args.eval {
+        let succeeded = run(eval, args, session);
 
-        match parser.current()? {
-            Token::EOF => break Ok(()),
-            Token::Op(';') => {
-                parser.advance()?;
-            },
-            Token::Def => handle_definition(&mut parser),
-            Token::Extern => handle_extern(&mut parser),
-            _ => handle_toplevel_expr(&mut parser),
+        if !args.interactive_after {
+            return Ok(if succeeded {
+                ExitCode::SUCCESS
+            } else {
+                ExitCode::FAILURE
+            });
         }
     }
+
+    loop {
+        write!(session.out, "\n?> ")?;
+        session.out.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 || line.starts_with("exit") || line.starts_with("quit") {
+            break Ok(ExitCode::SUCCESS);
+        } else if line.chars().all(char::is_whitespace) {
+            continue;
+        }
+
+        run(&line, args, session);
+    }
 }
 
 // ============================================================================
@@ -170,27 +172,628 @@ fn main() -> io::Result<()> {
 
 use std::io::Write as _;
 
-fn handle_definition(parser: &mut Parser) {
-    match parser.parse_definition() {
-        Ok(func) => {
-            eprintln!("Parsed a function definition: {}", func.proto.name);
+/// The state kept from one input to the next.
+struct Session {
+    /// Parses each input in turn. Operators declared with `binary` stay
+    /// defined for the whole session.
+    parser:   Parser<'static>,
+    /// Evaluates top-level expressions when the JIT isn't used, holding the
+    /// functions defined so far.
+    interp:   Interp,
+    /// The prototype of each function declared so far, by name, which the
+    /// calls of later items are resolved against.
+    protos:   HashMap<String, Prototype>,
+    /// The functions declared with `def f(x);` that have not been defined
+    /// yet.
+    pending:  HashSet<String>,
+    /// Compiles every item into one module, and runs top-level expressions.
+    #[cfg(feature = "llvm")]
+    compiler: Compiler<'static>,
+    /// Where the prompt, the values of top-level expressions and the debug
+    /// output go. Errors go to standard error.
+    out:      Box<dyn io::Write>,
+}
+
+impl Session {
+    fn new(args: &Args) -> Self { Self::with_output(args, io::stdout) }
+
+    /// Creates a session writing to the writers `out` makes: one for its own
+    /// output, and one for the output of the interpreter's builtins.
+    fn with_output<W: io::Write + 'static>(args: &Args, out: impl Fn() -> W) -> Self {
+        // The context lives as long as the program.
+        #[cfg(feature = "llvm")]
+        let compiler = Compiler::new(Box::leak(Box::new(Context::create())), "repl")
+            .with_optimizations(args.opt_level);
+
+        let interp = if args.no_builtins {
+            Interp::without_builtins()
+        } else {
+            Interp::new()
+        };
+
+        // The builtins are declared up front, so calls to them check without
+        // an `extern`. Compiled code calls the functions exported above.
+        let protos: HashMap<_, _> = interp
+            .builtins()
+            .map(|proto| (proto.name.clone(), proto))
+            .collect();
+        #[cfg(feature = "llvm")]
+        for proto in protos.values() {
+            compiler
+                .compile_prototype(proto)
+                .expect("Builtins use the default calling convention.");
+        }
+
+        Self {
+            parser: Parser::with_default_precedence("").expect("Empty input always lexes."),
+            interp: interp.with_output(out()),
+            protos,
+            pending: HashSet::new(),
+            #[cfg(feature = "llvm")]
+            compiler,
+            out: Box::new(out()),
+        }
+    }
+}
+
+/// Parses and handles every item in `input`, up to the first error. The
+/// items before it are still handled. Returns whether there was no error.
+/// The interpreter runs the items once they're all parsed, and the compiler
+/// handles each as soon as it's checked.
+fn run(input: &str, args: &Args, session: &mut Session) -> bool {
+    if args.display_lexer_output {
+        display_tokens(input, session);
+    }
+
+    if let Err(e) = session.parser.reset(input) {
+        report(input, &e);
+        return false;
+    }
+
+    let mut items = Vec::new();
+    let error = loop {
+        match session.parser.parse_one() {
+            Ok(Some(mut func)) => {
+                let declared = (session.protos.clone(), session.pending.clone());
+                if let Err(e) = check_item(&mut func, session) {
+                    break Some(e);
+                } else if interpreted(args) {
+                    items.push(func);
+                } else if let Err(e) = handle_item(&func, args, session) {
+                    // Compiled items are handled as they're checked, so that one
+                    // the compiler rejects, like a redefinition, declares nothing.
+                    (session.protos, session.pending) = declared;
+                    break Some(e);
+                }
+            },
+            Ok(None) => break None,
+            Err(e) => break Some(e),
+        }
+    };
+
+    let handled = handle_items(&items, args, session).inspect_err(|e| report(input, e));
+    if let Some(e) = &error {
+        report(input, e);
+    }
+    handled.is_ok() && error.is_none()
+}
+
+/// Parses the whole file at `path`, then handles each of its items. Returns
+/// the exit status of the program: 1 if the file can't be read, parsed or
+/// run, and otherwise the status its last value maps to with `exit_status`.
+fn run_file(path: &Path, args: &Args, session: &mut Session) -> u8 {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Error reading {}: {e}", path.display());
+            return 1;
+        },
+    };
+
+    if args.display_lexer_output {
+        display_tokens(&source, session);
+    }
+
+    let items = session
+        .parser
+        .reset(&source)
+        .and_then(|()| session.parser.parse_all())
+        .and_then(|mut items| check_program(&mut items, session).map(|()| items));
+
+    match items.and_then(|items| handle_items(&items, args, session)) {
+        Ok(value) => exit_status(value.as_ref()),
+        Err(e) => {
+            report(&source, &e);
+            1
         },
-        Err(e) => eprintln!("Error in definition: {:?}", e),
     }
 }
 
-fn handle_extern(parser: &mut Parser) {
-    match parser.parse_extern() {
-        Ok(proto) => eprintln!("Parsed an extern: {}", proto.proto.name),
-        Err(e) => eprintln!("Error parsing extern: {:?}", e),
+/// Maps the value of the last top-level expression of a file to the exit
+/// status of the program, so that scripts can signal success or failure to
+/// the shell. A number is truncated toward zero and clamped to `255`, so
+/// `2.7` exits with 2 and `300` with 255. Negative numbers, NaN and other
+/// values exit with 1, like a failure, so that a script ending in `-1` fails. A
+/// file without top-level expressions exits with 0.
+fn exit_status(value: Option<&Value>) -> u8 {
+    match value {
+        None => 0,
+        Some(&Value::Number(value)) if value >= 0.0 => value as u8,
+        Some(_) => 1,
     }
 }
 
-fn handle_toplevel_expr(parser: &mut Parser) {
-    match parser.parse_toplevel_expr() {
-        Ok(func) => {
-            eprintln!("Parsed a top-level expr");
+/// Resolves the named arguments of `func` against the prototypes declared so
+/// far and checks its calls against them, then records its prototype for the
+/// items that follow. A definition must also match its forward declaration,
+/// if any. A function may call itself, so its own prototype is already known
+/// while it's checked. An item that fails the checks declares nothing.
+fn check_item(func: &mut Function, session: &mut Session) -> Result<(), Error> {
+    let check_body = |func: &mut Function, protos: &HashMap<String, Prototype>| {
+        analysis::resolve_named_args_in(func, protos)?;
+        analysis::check_calls_in(func, protos)
+    };
+
+    if func.is_anon {
+        return check_body(func, &session.protos);
+    }
+
+    let name = func.proto.name.clone();
+    if func.body.is_some()
+        && session.pending.contains(&name)
+        && let Some(decl) = session.protos.get(&name)
+    {
+        analysis::check_forward_decl(decl, &func.proto)?;
+    }
+
+    let previous = session.protos.insert(name.clone(), func.proto.clone());
+
+    let result = check_body(func, &session.protos);
+    match &result {
+        Ok(()) if func.body.is_some() => {
+            session.pending.remove(&name);
+        },
+        Ok(()) if !func.is_extern => {
+            session.pending.insert(name);
+        },
+        Ok(()) => (),
+        Err(_) => {
+            match previous {
+                Some(proto) => session.protos.insert(name, proto),
+                None => session.protos.remove(&name),
+            };
         },
-        Err(e) => eprintln!("Error: {:?}", e),
+    }
+    result
+}
+
+/// Checks each of `items` like `check_item`, after checking that its forward
+/// declarations are all defined and recording all of its prototypes, since
+/// the items of a file may call functions declared further down.
+fn check_program(items: &mut [Function], session: &mut Session) -> Result<(), Error> {
+    analysis::check_forward_decls(items)?;
+    session.protos.extend(
+        items
+            .iter()
+            .filter(|func| !func.is_anon)
+            .map(|func| (func.proto.name.clone(), func.proto.clone())),
+    );
+
+    items.iter_mut().try_for_each(|func| check_item(func, session))
+}
+
+/// Prints `e`, showing the part of `source` it points at, if any.
+fn report(source: &str, e: &Error) {
+    match e.span() {
+        Some(span) => eprint!("{}", diagnostics::render(source, span, &e.message())),
+        None => eprintln!("Error: {e}"),
+    }
+}
+
+fn display_tokens(input: &str, session: &mut Session) {
+    // Lexing errors are reported by the parser.
+    if let Ok(tokens) = tokenize(input) {
+        println_to!(session, "-> Attempting to parse lexed input: \n{tokens:?}\n");
+    }
+}
+
+/// Handles `items` in order, and returns the value of the last top-level
+/// expression among them, if any. Stops at the first error, which is returned
+/// once the items before it have been handled. The interpreter runs them as
+/// one program, so that they may call functions defined after them.
+fn handle_items(items: &[Function], args: &Args, session: &mut Session) -> Result<Option<Value>, Error> {
+    let mut last = None;
+
+    if !interpreted(args) {
+        for func in items {
+            last = handle_item(func, args, session)?.or(last);
+        }
+        return Ok(last);
+    }
+
+    session.interp.define_ahead(items);
+    for func in items {
+        display_item(func, args, session);
+        match session.interp.eval_item(func)? {
+            Some(value) => {
+                println_to!(session, "=> {value}");
+                last = Some(value);
+            },
+            None => announce(func),
+        }
+    }
+
+    Ok(last)
+}
+
+/// Handles `func`, returning its value if it's a top-level expression.
+fn handle_item(func: &Function, args: &Args, session: &mut Session) -> Result<Option<Value>, Error> {
+    display_item(func, args, session);
+
+    if func.is_anon {
+        Ok(handle_toplevel_expr(func, args, session)?.map(Value::Number))
+    } else {
+        handle_declaration(func, args, session)?;
+        Ok(None)
+    }
+}
+
+/// Prints the warnings about `func`, and whatever the debug flags ask for.
+fn display_item(func: &Function, args: &Args, session: &mut Session) {
+    for warning in analysis::check_for_steps(func) {
+        eprintln!("{warning}");
+    }
+
+    if args.display_parser_output {
+        match &func.body {
+            Some(body) if func.is_anon => println_to!(session, "-> Expression parsed: \n{body:?}\n"),
+            _ => println_to!(session, "-> Function parsed: \n{func:?}\n"),
+        }
+    }
+
+    if args.display_ast
+        && let Some(body) = &func.body
+    {
+        if func.is_anon {
+            println_to!(session, "-> Expression tree: \n{}\n", body.to_tree_string());
+        } else {
+            println_to!(
+                session,
+                "-> Tree of {}: \n{}\n",
+                func.proto,
+                body.to_tree_string()
+            );
+        }
+    }
+}
+
+/// Compiles `func` into the session's module, printing its IR if asked to.
+#[cfg(feature = "llvm")]
+fn compile(func: &Function, args: &Args, session: &mut Session) -> Result<FunctionValue<'static>, Error> {
+    let function = session.compiler.compile_fn(func)?;
+    if args.display_compiler_output {
+        println_to!(session, "-> Compiled to IR: \n{}", function.print_to_string());
+    }
+    Ok(function)
+}
+
+/// Returns whether items go to the interpreter rather than the compiler.
+fn interpreted(args: &Args) -> bool { args.interp || cfg!(not(feature = "llvm")) }
+
+fn handle_declaration(func: &Function, args: &Args, session: &mut Session) -> Result<(), Error> {
+    #[cfg(feature = "llvm")]
+    compile(func, args, session)?;
+
+    announce(func);
+    Ok(())
+}
+
+/// Prints what kind of function `func` declares.
+fn announce(func: &Function) {
+    if func.is_extern {
+        eprintln!("Parsed an extern: {}", func.proto.name);
+    } else if func.body.is_some() {
+        eprintln!("Parsed a function definition: {}", func.proto.name);
+    } else {
+        eprintln!("Parsed a forward declaration: {}", func.proto.name);
+    }
+}
+
+fn handle_toplevel_expr(func: &Function, args: &Args, session: &mut Session) -> Result<Option<f64>, Error> {
+    #[cfg(feature = "llvm")]
+    let value = {
+        let function = compile(func, args, session)?;
+        let value = session.compiler.run_toplevel(function)?;
+        println_to!(session, "=> {value}");
+        Some(value)
+    };
+    #[cfg(not(feature = "llvm"))]
+    let value = None;
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use kaleidoscope::testing::{Buffer, parse_expr, parse_items};
+
+    use super::*;
+
+    /// Creates a session for `args` writing all of its output to the buffer
+    /// it's returned with.
+    fn capture(args: &Args) -> (Buffer, Session) {
+        let out = Buffer::default();
+        let session = Session::with_output(args, || out.clone());
+        (out, session)
+    }
+
+    #[test]
+    fn repl_script_definitions_stay_usable() {
+        let path = std::env::temp_dir().join(format!("kaleidoscope-{}.ks", std::process::id()));
+        std::fs::write(&path, "def sq(x) x * x").unwrap();
+
+        let args = Args::parse_from([
+            "kaleidoscope".as_ref(),
+            "--interp".as_ref(),
+            "--repl-script".as_ref(),
+            path.as_os_str(),
+        ]);
+        let (out, mut session) = capture(&args);
+        start(&args, "sq(3)\n".as_bytes(), &mut session).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(out.contents(), "\n?> => 9\n\n?> ");
+    }
+
+    #[test]
+    fn eval_definitions_persist_into_the_prompt() {
+        let args = Args::parse_from([
+            "kaleidoscope",
+            "--interp",
+            "-e",
+            "def sq(x) x*x",
+            "--interactive-after",
+        ]);
+        let (out, mut session) = capture(&args);
+        start(&args, "sq(4)\nquit\nsq(5)\n".as_bytes(), &mut session).unwrap();
+        assert_eq!(out.contents(), "\n?> => 16\n\n?> ");
+
+        // Without `--interactive-after`, the prompt is skipped.
+        let args = Args::parse_from(["kaleidoscope", "--interp", "-e", "def sq(x) x*x; sq(2)"]);
+        let (out, mut session) = capture(&args);
+        start(&args, "sq(4)\n".as_bytes(), &mut session).unwrap();
+        assert_eq!(out.contents(), "=> 4\n");
+    }
+
+    #[test]
+    fn interactive_after_requires_eval() {
+        assert!(Args::try_parse_from(["kaleidoscope", "--interactive-after"]).is_err());
+    }
+
+    #[test]
+    fn runs_files_without_the_prompt() {
+        let path = std::env::temp_dir().join(format!("kaleidoscope-{}.k", std::process::id()));
+        std::fs::write(&path, "def sq(x) x*x;\nsq(3)").unwrap();
+
+        let args = Args::parse_from([
+            "kaleidoscope".as_ref(),
+            "--interp".as_ref(),
+            "--file".as_ref(),
+            path.as_os_str(),
+        ]);
+        let mut session = Session::new(&args);
+        assert_eq!(run_file(&path, &args, &mut session), 9);
+        assert_eq!(eval(&mut session, "sq(4)"), 16.0);
+
+        std::fs::write(&path, "def sq(x x").unwrap();
+        assert_eq!(run_file(&path, &args, &mut session), 1);
+
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(run_file(&path, &args, &mut session), 1);
+    }
+
+    #[test]
+    fn file_conflicts_with_eval_and_repl_script() {
+        assert!(Args::try_parse_from(["kaleidoscope", "--file", "a.k", "-e", "1"]).is_err());
+        assert!(Args::try_parse_from(["kaleidoscope", "--file", "a.k", "--repl-script", "b.k"]).is_err());
+    }
+
+    #[test]
+    fn debug_flags_compose_with_eval() {
+        let args = Args::parse_from([
+            "kaleidoscope",
+            "--interp",
+            "--dl",
+            "--dp",
+            "--ast",
+            "-e",
+            "def inc(x) x + 1; inc(1)",
+        ]);
+        let (out, mut session) = capture(&args);
+        start(&args, io::empty(), &mut session).unwrap();
+        let out = out.contents();
+
+        // Each flag prints its part, in order, without getting in the way of running
+        // the input.
+        let parts = [
+            "-> Attempting to parse lexed input: \n[Keyword(Def), Ident(\"inc\"), LParen",
+            "-> Function parsed: \nFunction { proto: Prototype { name: \"inc\"",
+            "-> Tree of inc(x): \n",
+            "-> Expression parsed: \n",
+            "-> Expression tree: \n",
+            "=> 2\n",
+        ];
+        let mut rest = out.as_str();
+        for part in parts {
+            let at = rest
+                .find(part)
+                .unwrap_or_else(|| panic!("`{part}` is missing from:\n{out}"));
+            rest = &rest[at + part.len()..];
+        }
+        assert!(out.ends_with("=> 2\n"), "{out}");
+    }
+
+    #[test]
+    fn ast_flag_is_parsed() {
+        assert!(Args::parse_from(["kaleidoscope", "--ast"]).display_ast);
+        assert!(!Args::parse_from(["kaleidoscope"]).display_ast);
+    }
+
+    fn eval(session: &mut Session, input: &str) -> Value {
+        session.interp.eval(&parse_expr(input)).unwrap()
+    }
+
+    #[test]
+    fn interprets_without_llvm() {
+        let args = Args::parse_from(["kaleidoscope"]);
+        assert_eq!(interpreted(&args), cfg!(not(feature = "llvm")));
+        assert!(interpreted(&Args::parse_from(["kaleidoscope", "--interp"])));
+
+        let args = Args::parse_from(["kaleidoscope", "--interp"]);
+        let mut session = Session::new(&args);
+        run("def twice(x) x * 2", &args, &mut session);
+        assert_eq!(eval(&mut session, "twice(21)"), 42.0);
+    }
+
+    #[test]
+    fn interpreted_definitions_are_callable_later() {
+        let args = Args::parse_from(["kaleidoscope", "--interp"]);
+        let (out, mut session) = capture(&args);
+
+        run("def sq(x) x*x", &args, &mut session);
+        run("sq(5)", &args, &mut session);
+        assert_eq!(out.contents(), "=> 25\n");
+
+        // Redefining a function replaces it for the following inputs.
+        run("def sq(x) x*x*x", &args, &mut session);
+        run("sq(5)", &args, &mut session);
+        assert_eq!(out.contents(), "=> 25\n=> 125\n");
+    }
+
+    #[test]
+    fn resolves_named_args_against_earlier_items() {
+        let args = Args::parse_from(["kaleidoscope", "--interp"]);
+        let mut session = Session::new(&args);
+        run("def sub(a, b) a - b", &args, &mut session);
+
+        let mut call = item("sub(b = 1, a = 5)");
+        check_item(&mut call, &mut session).unwrap();
+        assert_eq!(session.interp.eval(call.body.as_ref().unwrap()).unwrap(), 4.0);
+    }
+
+    fn item(input: &str) -> Function { parse_items(input).remove(0) }
+
+    #[test]
+    fn checks_calls_against_earlier_items() {
+        let args = Args::parse_from(["kaleidoscope", "--interp"]);
+        let mut session = Session::new(&args);
+
+        // Builtins are declared up front, but still checked like any other function.
+        check_item(&mut item("putchard(65)"), &mut session).unwrap();
+        assert!(check_item(&mut item("putchard(65, 66)"), &mut session).is_err());
+        check_item(&mut item("extern putchard(x)"), &mut session).unwrap();
+        check_item(&mut item("putchard(65)"), &mut session).unwrap();
+
+        // A rejected definition doesn't declare its function.
+        assert!(check_item(&mut item("def f(x) g(x)"), &mut session).is_err());
+        assert!(check_item(&mut item("f(1)"), &mut session).is_err());
+    }
+
+    #[test]
+    fn no_builtins_leaves_out_printd() {
+        let input = "extern printd(x); printd(1)";
+
+        let args = Args::parse_from(["kaleidoscope", "--interp", "--no-builtins"]);
+        let (out, mut session) = capture(&args);
+        run(input, &args, &mut session);
+        assert_eq!(out.contents(), "");
+        let err = session.interp.eval(&item("printd(1)").body.unwrap()).unwrap_err();
+        assert!(err.to_string().contains("Unknown function `printd`"), "{err}");
+
+        let args = Args::parse_from(["kaleidoscope", "--interp"]);
+        let (out, mut session) = capture(&args);
+        run(input, &args, &mut session);
+        assert_eq!(out.contents(), "1\n=> 1\n");
+    }
+
+    #[test]
+    fn calls_builtins_without_an_extern() {
+        let args = Args::parse_from(["kaleidoscope", "--interp"]);
+        let (out, mut session) = capture(&args);
+        run("printd(42)", &args, &mut session);
+        assert_eq!(out.contents(), "42\n=> 42\n");
+
+        // Without builtins, there is nothing to call.
+        let args = Args::parse_from(["kaleidoscope", "--interp", "--no-builtins"]);
+        let (_, mut session) = capture(&args);
+        let err = check_item(&mut item("printd(42)"), &mut session).unwrap_err();
+        assert!(err.to_string().contains("unknown function"), "{err}");
+    }
+
+    #[test]
+    fn runs_programs_through_the_interpreter() {
+        let args = Args::parse_from(["kaleidoscope", "--interp"]);
+        let (out, mut session) = capture(&args);
+
+        run("def sq(x) x*x; sq(2); sq(3)", &args, &mut session);
+        assert_eq!(out.contents(), "=> 4\n=> 9\n");
+
+        // A file is checked as a whole, so `cube` can be called before it's defined.
+        let path = std::env::temp_dir().join(format!("kaleidoscope-program-{}.k", std::process::id()));
+        std::fs::write(&path, "cube(2);\ndef cube(x) x*sq(x);\ncube(3)").unwrap();
+        assert_eq!(run_file(&path, &args, &mut session), 27);
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(out.contents(), "=> 4\n=> 9\n=> 8\n=> 27\n");
+    }
+
+    #[test]
+    fn prints_the_values_before_a_runtime_error() {
+        let args = Args::parse_from(["kaleidoscope", "--interp"]);
+        let (out, mut session) = capture(&args);
+
+        let items = parse_items("def f(x) f(x); printd(7); 2; f(1); 3");
+        let err = handle_items(&items, &args, &mut session).unwrap_err();
+        assert!(err.to_string().contains("maximum call depth"), "{err}");
+        assert_eq!(out.contents(), "7\n=> 7\n=> 2\n");
+    }
+
+    #[test]
+    fn maps_the_last_value_to_an_exit_status() {
+        let status = |value: f64| exit_status(Some(&Value::Number(value)));
+        assert_eq!(status(0.0), 0);
+        assert_eq!(status(2.7), 2);
+        assert_eq!(status(-1.0), 1);
+        assert_eq!(status(-0.5), 1);
+        assert_eq!(status(300.0), 255);
+        assert_eq!(status(f64::NAN), 1);
+        assert_eq!(exit_status(Some(&Value::Tuple(vec![0.0.into()]))), 1);
+        assert_eq!(exit_status(None), 0);
+    }
+
+    #[cfg(feature = "llvm")]
+    #[test]
+    fn runs_toplevel_expressions_with_the_jit() {
+        let args = Args::parse_from(["kaleidoscope"]);
+        let (out, mut session) = capture(&args);
+        run("4+5;", &args, &mut session);
+        assert_eq!(out.contents(), "=> 9\n");
+
+        // Each top-level expression replaces the anonymous function of the one before.
+        run("def sq(x) x*x; sq(4)", &args, &mut session);
+        run("sq(5)", &args, &mut session);
+        assert_eq!(out.contents(), "=> 9\n=> 16\n=> 25\n");
+    }
+
+    #[cfg(feature = "llvm")]
+    #[test]
+    fn rejected_redefinitions_keep_the_first_prototype() {
+        let args = Args::parse_from(["kaleidoscope"]);
+        let (out, mut session) = capture(&args);
+        assert!(run("def f(x) x", &args, &mut session));
+        assert!(!run("def f(x, y) x", &args, &mut session));
+
+        assert_eq!(session.protos["f"].args, ["x"]);
+        assert!(run("f(7)", &args, &mut session));
+        assert_eq!(out.contents(), "=> 7\n");
     }
 }
@@ -0,0 +1,148 @@
+//! Binary operator precedence and associativity
+
+use core::fmt;
+use std::collections::HashMap;
+
+/// The spelling of a binary operator: either a single character such as `+`,
+/// or two such as `<=`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Operator {
+    first:  char,
+    second: Option<char>,
+}
+
+impl Operator {
+    /// Creates a new single-character `Operator`.
+    pub const fn new(op: char) -> Self {
+        Self {
+            first:  op,
+            second: None,
+        }
+    }
+
+    /// Creates a new two-character `Operator`, such as `<=` from `'<'` and
+    /// `'='`.
+    pub const fn pair(first: char, second: char) -> Self {
+        Self {
+            first,
+            second: Some(second),
+        }
+    }
+
+    /// Returns the character of a single-character operator.
+    pub const fn as_char(self) -> Option<char> {
+        match self.second {
+            None => Some(self.first),
+            Some(_) => None,
+        }
+    }
+}
+
+impl From<char> for Operator {
+    fn from(op: char) -> Self { Self::new(op) }
+}
+
+impl PartialEq<char> for Operator {
+    fn eq(&self, other: &char) -> bool { self.as_char() == Some(*other) }
+}
+
+impl fmt::Display for Operator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.first)?;
+        match self.second {
+            Some(second) => write!(f, "{second}"),
+            None => Ok(()),
+        }
+    }
+}
+
+impl fmt::Debug for Operator {
+    /// Formats the operator like a `char` literal, e.g. `'+'` or `'<='`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "'{self}'") }
+}
+
+/// The side an operator groups towards when chained with operators of the
+/// same precedence: `a - b - c` is `(a - b) - c` for a left-associative `-`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Assoc {
+    #[default]
+    Left,
+    Right,
+}
+
+/// The precedence and associativity of a binary operator, as stored in the
+/// precedence tables of the parser and the pretty-printer.
+pub type Fixity = (i32, Assoc);
+
+/// A binary operator along with how tightly it binds its operands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct OperatorInfo {
+    pub op:    Operator,
+    pub prec:  i32,
+    pub assoc: Assoc,
+}
+
+impl OperatorInfo {
+    /// Creates a new left-associative `OperatorInfo`.
+    pub const fn new(op: Operator, prec: i32) -> Self {
+        Self {
+            op,
+            prec,
+            assoc: Assoc::Left,
+        }
+    }
+
+    /// Looks up `op` in the precedence table `prec`. Operators missing from
+    /// the table bind the loosest, with a precedence of -1, and are
+    /// left-associative.
+    pub fn lookup(prec: &HashMap<Operator, Fixity>, op: Operator) -> Self {
+        match prec.get(&op) {
+            Some(&(prec, assoc)) => Self { op, prec, assoc },
+            None => Self::new(op, -1),
+        }
+    }
+
+    /// Returns a value indicating whether or not `self`, following `other` as
+    /// in `a other b self c`, takes `b` as its left operand: it binds tighter
+    /// if its precedence is higher, or if it's equal and `self` is
+    /// right-associative.
+    pub fn binds_tighter_than(&self, other: &Self) -> bool {
+        self.prec > other.prec || (self.prec == other.prec && self.assoc == Assoc::Right)
+    }
+
+    /// Returns the lowest precedence an operator following the right operand
+    /// of `self` must have to take that operand away from it.
+    pub const fn rhs_min_prec(&self) -> i32 {
+        match self.assoc {
+            Assoc::Left => self.prec + 1,
+            Assoc::Right => self.prec,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn binds_tighter_than_follows_precedence_then_assoc() {
+        let add = OperatorInfo::new(Operator::new('+'), 20);
+        let sub = OperatorInfo::new(Operator::new('-'), 20);
+        let mul = OperatorInfo::new(Operator::new('*'), 40);
+        let pow = OperatorInfo {
+            assoc: Assoc::Right,
+            ..OperatorInfo::new(Operator::new('^'), 60)
+        };
+
+        // `a + b - c` is `(a + b) - c`.
+        assert!(!sub.binds_tighter_than(&add));
+        // `a ^ b ^ c` is `a ^ (b ^ c)`.
+        assert!(pow.binds_tighter_than(&pow));
+        // `a + b * c` is `a + (b * c)`, and `a * b + c` is `(a * b) + c`.
+        assert!(mul.binds_tighter_than(&add));
+        assert!(!add.binds_tighter_than(&mul));
+
+        assert_eq!(add.rhs_min_prec(), 21);
+        assert_eq!(pow.rhs_min_prec(), 60);
+    }
+}
@@ -0,0 +1,393 @@
+//! Optimization passes over the AST
+
+use crate::ast::Expr;
+use crate::operator::Operator;
+use crate::visit::Visitor;
+
+/// Replaces every arithmetic `Binary` node (`+`, `-`, `*` or `/`) whose
+/// operands fold to numbers by the resulting `Number`, so that
+/// `2 * (3 + 4)` becomes `14`. Divisions by zero are left unfolded.
+///
+/// A tree with nothing to fold is returned as is, without being rebuilt.
+pub fn fold_constants(expr: Expr) -> Expr { folded(&expr).unwrap_or(expr) }
+
+/// Folds `expr` like `fold_constants`, or returns `None` if nothing in it
+/// folds. Only the nodes above a folded one are rebuilt.
+fn folded(expr: &Expr) -> Option<Expr> {
+    let children = map_children(expr, folded);
+
+    if let Expr::Binary { op, lhs, rhs, span } = children.as_ref().unwrap_or(expr)
+        && let (Expr::Number(l, _), Expr::Number(r, _)) = (&**lhs, &**rhs)
+    {
+        let value = match op.as_char() {
+            Some('+') => Some(l + r),
+            Some('-') => Some(l - r),
+            Some('*') => Some(l * r),
+            Some('/') if *r != 0.0 => Some(l / r),
+            _ => None,
+        };
+
+        if let Some(value) = value {
+            return Some(Expr::Number(value, *span));
+        }
+    }
+
+    children
+}
+
+/// Rebuilds `expr` with each of its direct children replaced by what `f`
+/// returns for it, keeping a copy of those `f` returns `None` for. Returns
+/// `None` without copying anything if `f` changes none of them.
+fn map_children(expr: &Expr, mut f: impl FnMut(&Expr) -> Option<Expr>) -> Option<Expr> {
+    let keep = |changed: Option<Expr>, expr: &Expr| Box::new(changed.unwrap_or_else(|| expr.clone()));
+
+    match expr {
+        Expr::Binary { op, lhs, rhs, span } => {
+            let (new_lhs, new_rhs) = (f(lhs), f(rhs));
+            if new_lhs.is_none() && new_rhs.is_none() {
+                return None;
+            }
+            Some(Expr::Binary {
+                op:   *op,
+                lhs:  keep(new_lhs, lhs),
+                rhs:  keep(new_rhs, rhs),
+                span: *span,
+            })
+        },
+        Expr::Call { name, args, span } => Some(Expr::Call {
+            name: name.clone(),
+            args: map_all(args, f)?,
+            span: *span,
+        }),
+        Expr::Do(stmts, span) => Some(Expr::Do(map_all(stmts, f)?, *span)),
+        Expr::For {
+            var,
+            start,
+            end,
+            step,
+            body,
+            span,
+        } => {
+            let (new_start, new_end) = (f(start), f(end));
+            let new_step = step.as_deref().and_then(&mut f);
+            let new_body = f(body);
+            if new_start.is_none() && new_end.is_none() && new_step.is_none() && new_body.is_none() {
+                return None;
+            }
+            Some(Expr::For {
+                var:   var.clone(),
+                start: keep(new_start, start),
+                end:   keep(new_end, end),
+                step:  step.as_deref().map(|step| keep(new_step, step)),
+                body:  keep(new_body, body),
+                span:  *span,
+            })
+        },
+        Expr::If {
+            cond,
+            then,
+            alt,
+            span,
+        } => {
+            let (new_cond, new_then, new_alt) = (f(cond), f(then), f(alt));
+            if new_cond.is_none() && new_then.is_none() && new_alt.is_none() {
+                return None;
+            }
+            Some(Expr::If {
+                cond: keep(new_cond, cond),
+                then: keep(new_then, then),
+                alt:  keep(new_alt, alt),
+                span: *span,
+            })
+        },
+        Expr::NamedArg { name, value, span } => Some(Expr::NamedArg {
+            name:  name.clone(),
+            value: Box::new(f(value)?),
+            span:  *span,
+        }),
+        Expr::Seq(exprs, span) => Some(Expr::Seq(map_all(exprs, f)?, *span)),
+        Expr::Tuple(elems, span) => Some(Expr::Tuple(map_all(elems, f)?, *span)),
+        Expr::Var { bindings, body, span } => {
+            let inits: Vec<_> = bindings
+                .iter()
+                .map(|(_, init)| init.as_ref().and_then(&mut f))
+                .collect();
+            let new_body = f(body);
+            if new_body.is_none() && inits.iter().all(Option::is_none) {
+                return None;
+            }
+            Some(Expr::Var {
+                bindings: bindings
+                    .iter()
+                    .zip(inits)
+                    .map(|((name, init), new_init)| (name.clone(), new_init.or_else(|| init.clone())))
+                    .collect(),
+                body:     keep(new_body, body),
+                span:     *span,
+            })
+        },
+        Expr::Number(..) | Expr::Variable(..) => None,
+    }
+}
+
+/// Maps `exprs` with `f` like `map_children`, returning `None` if `f` changes
+/// none of them.
+fn map_all(exprs: &[Expr], mut f: impl FnMut(&Expr) -> Option<Expr>) -> Option<Vec<Expr>> {
+    let (i, first) = exprs
+        .iter()
+        .enumerate()
+        .find_map(|(i, expr)| f(expr).map(|changed| (i, changed)))?;
+
+    let mut mapped = exprs[..i].to_vec();
+    mapped.push(first);
+    mapped.extend(
+        exprs[i + 1..]
+            .iter()
+            .map(|expr| f(expr).unwrap_or_else(|| expr.clone())),
+    );
+    Some(mapped)
+}
+
+/// Substitutes the constant initializers of `var` bindings into the scope of
+/// the binding and folds the result, so that `var x = 5 in x * 2` becomes
+/// `10`. A binding without an initializer is the constant `0`.
+///
+/// A binding is only propagated if nothing in its scope assigns to it, and
+/// is then dropped from its `var`. Assignments to a shadowing binding of the
+/// same name count too, which keeps the check simple at the cost of missing
+/// some propagations.
+pub fn propagate_constants(expr: Expr) -> Expr { fold_constants(propagate_bindings(expr)) }
+
+/// Substitutes constant bindings like `propagate_constants`, but only folds
+/// the initializers of bindings, to find out whether they're constant.
+fn propagate_bindings(expr: Expr) -> Expr {
+    let propagate = |expr: Box<Expr>| Box::new(propagate_bindings(*expr));
+    let propagate_all = |exprs: Vec<Expr>| exprs.into_iter().map(propagate_bindings).collect();
+
+    match expr {
+        Expr::Binary { op, lhs, rhs, span } => Expr::Binary {
+            op,
+            lhs: propagate(lhs),
+            rhs: propagate(rhs),
+            span,
+        },
+        Expr::Call { name, args, span } => Expr::Call {
+            name,
+            args: propagate_all(args),
+            span,
+        },
+        Expr::Do(stmts, span) => Expr::Do(propagate_all(stmts), span),
+        Expr::For {
+            var,
+            start,
+            end,
+            step,
+            body,
+            span,
+        } => Expr::For {
+            var,
+            start: propagate(start),
+            end: propagate(end),
+            step: step.map(propagate),
+            body: propagate(body),
+            span,
+        },
+        Expr::If {
+            cond,
+            then,
+            alt,
+            span,
+        } => Expr::If {
+            cond: propagate(cond),
+            then: propagate(then),
+            alt: propagate(alt),
+            span,
+        },
+        Expr::NamedArg { name, value, span } => Expr::NamedArg {
+            name,
+            value: propagate(value),
+            span,
+        },
+        Expr::Seq(exprs, span) => Expr::Seq(propagate_all(exprs), span),
+        Expr::Tuple(elems, span) => Expr::Tuple(propagate_all(elems), span),
+        Expr::Var { bindings, body, span } => {
+            // Peel off the first binding, whose scope is a `var` of the rest.
+            let mut bindings = bindings.into_iter();
+            let Some((name, init)) = bindings.next() else {
+                return propagate_bindings(*body);
+            };
+            let rest = Expr::Var {
+                bindings: bindings.collect(),
+                body,
+                span,
+            };
+
+            let init = init.map(|init| fold_constants(propagate_bindings(init)));
+            let value = match &init {
+                Some(Expr::Number(value, _)) => Some(*value),
+                Some(_) => None,
+                None => Some(0.0),
+            };
+
+            match value {
+                Some(value) if !assigns(&name, &rest) => {
+                    propagate_bindings(substitute(&rest, &name, value).unwrap_or(rest))
+                },
+                _ => match propagate_bindings(rest) {
+                    Expr::Var {
+                        mut bindings, body, ..
+                    } => {
+                        bindings.insert(0, (name, init));
+                        Expr::Var { bindings, body, span }
+                    },
+                    body => Expr::Var {
+                        bindings: vec![(name, init)],
+                        body: Box::new(body),
+                        span,
+                    },
+                },
+            }
+        },
+        Expr::Number(..) | Expr::Variable(..) => expr,
+    }
+}
+
+/// Returns whether `expr` assigns to `name` anywhere.
+fn assigns(name: &str, expr: &Expr) -> bool {
+    let mut assigns = Assigns { name, found: false };
+    assigns.visit_expr(expr);
+    assigns.found
+}
+
+/// Looks for a `name = value` assignment.
+struct Assigns<'a> {
+    name:  &'a str,
+    found: bool,
+}
+
+impl Visitor for Assigns<'_> {
+    fn visit_binary(&mut self, op: Operator, lhs: &Expr, rhs: &Expr) {
+        if op.as_char() == Some('=') && matches!(lhs, Expr::Variable(name, _) if name == self.name) {
+            self.found = true;
+        }
+        self.visit_expr(lhs);
+        self.visit_expr(rhs);
+    }
+}
+
+/// Replaces the references to `name` in `expr` by `value`, except under a
+/// `for` or `var` that binds `name` again. Returns `None` if `expr` doesn't
+/// refer to `name`.
+fn substitute(expr: &Expr, name: &str, value: f64) -> Option<Expr> {
+    let sub = |expr: &Expr| substitute(expr, name, value);
+
+    match expr {
+        Expr::Variable(var, span) if var == name => Some(Expr::Number(value, *span)),
+        // The loop variable is not in scope in `start`.
+        Expr::For {
+            var,
+            start,
+            end,
+            step,
+            body,
+            span,
+        } if var == name => Some(Expr::For {
+            var:   var.clone(),
+            start: Box::new(sub(start)?),
+            end:   end.clone(),
+            step:  step.clone(),
+            body:  body.clone(),
+            span:  *span,
+        }),
+        Expr::Var { bindings, body, span } => {
+            let mut shadowed = false;
+            let inits: Vec<_> = bindings
+                .iter()
+                .map(|(var, init)| {
+                    let init = init.as_ref().filter(|_| !shadowed).and_then(sub);
+                    shadowed |= var == name;
+                    init
+                })
+                .collect();
+            let new_body = if shadowed { None } else { sub(body) };
+            if new_body.is_none() && inits.iter().all(Option::is_none) {
+                return None;
+            }
+
+            Some(Expr::Var {
+                bindings: bindings
+                    .iter()
+                    .zip(inits)
+                    .map(|((var, init), new_init)| (var.clone(), new_init.or_else(|| init.clone())))
+                    .collect(),
+                body:     Box::new(new_body.unwrap_or_else(|| (**body).clone())),
+                span:     *span,
+            })
+        },
+        _ => map_children(expr, sub),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::parse_expr;
+
+    fn fold(input: &str) -> String { fold_constants(parse_expr(input)).to_string() }
+
+    fn propagate(input: &str) -> String { propagate_constants(parse_expr(input)).to_string() }
+
+    #[test]
+    fn folds_arithmetic() {
+        assert_eq!(fold("2 * (3 + 4)"), "14");
+        assert_eq!(fold("f(1 + 1, 8 / 2)"), "f(2, 4)");
+        assert_eq!(fold("x + 2 * 3"), "(x + 6)");
+    }
+
+    #[test]
+    fn leaves_variables_and_divisions_by_zero_alone() {
+        assert_eq!(fold("x * (y + 1)"), "(x * (y + 1))");
+        assert_eq!(fold("1 / 0"), "(1 / 0)");
+        assert_eq!(fold("1 < 2"), "(1 < 2)");
+    }
+
+    #[test]
+    fn changes_nothing_in_trees_without_constants() {
+        for input in [
+            "x * (y + f(z, 1))",
+            "for i = 0, i < n, 1 in do { printd(i); i / 0 }",
+            "var a = b, c in if a then (a, c) else g(n = 2)",
+        ] {
+            assert!(folded(&parse_expr(input)).is_none(), "{input}");
+            assert!(substitute(&parse_expr(input), "w", 1.0).is_none(), "{input}");
+        }
+
+        assert_eq!(
+            fold("for i = 0, i < n in x : 1 + 1"),
+            "(for i = 0, (i < n) in (x : 2))"
+        );
+        assert_eq!(fold("var a = 2 * 3, b in (a, 1 - 1)"), "(var a = 6, b in (a, 0))");
+    }
+
+    #[test]
+    fn propagates_unmutated_bindings() {
+        assert_eq!(propagate("var x = 5 in x * 2"), "10");
+        assert_eq!(propagate("var x = 2, y = x + 1 in x * y"), "6");
+        assert_eq!(propagate("var x in x + 1"), "1");
+        assert_eq!(
+            propagate("var x = 5 in var x = y in x + 1"),
+            propagate("var x = y in x + 1")
+        );
+    }
+
+    #[test]
+    fn does_not_propagate_mutated_bindings() {
+        let expr = "var x = 5 in (x = x + 1) : x * 2";
+        assert_eq!(propagate(expr), fold(expr));
+        assert_eq!(
+            propagate("var x = 1, y = 2 in (y = x) : y"),
+            propagate("var y = 2 in (y = 1) : y")
+        );
+        assert_eq!(propagate("var x = f(1) in x * 2"), fold("var x = f(1) in x * 2"));
+    }
+}
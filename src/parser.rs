@@ -1,46 +1,272 @@
 //! The Kaleidoscope Parser
 
-use std::collections::HashMap;
-use std::io;
+use core::ops::{Deref, DerefMut};
+use std::collections::{BTreeMap, HashMap};
 
 use crate::ast::{Expr, Function, Prototype};
+use crate::error::{Error, ParseErrorKind as PE, Result};
 use crate::lexer::Lexer;
-use crate::token::Token;
+use crate::operator::{Assoc, Fixity, Operator, OperatorInfo};
+use crate::span::{Span, Spanned};
+use crate::token::{Keyword, Token};
 
-enum PE {
-    Syntax,
-    Eof,
-}
 const FUNC_NAME: &str = "anon";
 
+/// The built-in binary operators, with their precedence and associativity.
+/// Only `=` and `^` are right-associative, so that `a = b = c` is
+/// `a = (b = c)` and `2 ^ 3 ^ 2` is `2 ^ (3 ^ 2)`.
+pub const DEFAULT_PRECEDENCE: &[(Operator, Fixity)] = &[
+    (Operator::new(':'), (1, Assoc::Left)),
+    (Operator::new('='), (2, Assoc::Right)),
+    (Operator::pair('=', '='), (5, Assoc::Left)),
+    (Operator::pair('!', '='), (5, Assoc::Left)),
+    (Operator::new('<'), (10, Assoc::Left)),
+    (Operator::new('>'), (10, Assoc::Left)),
+    (Operator::pair('<', '='), (10, Assoc::Left)),
+    (Operator::pair('>', '='), (10, Assoc::Left)),
+    (Operator::new('+'), (20, Assoc::Left)),
+    (Operator::new('-'), (20, Assoc::Left)),
+    (Operator::new('*'), (40, Assoc::Left)),
+    (Operator::new('/'), (40, Assoc::Left)),
+    (Operator::new('%'), (40, Assoc::Left)),
+    (Operator::new('^'), (60, Assoc::Right)),
+];
+
+/// Builds a `Parser` with its own precedence table, starting from
+/// `DEFAULT_PRECEDENCE`:
+///
+/// ```
+/// # use kaleidoscope::parser::ParserBuilder;
+/// let mut parser = ParserBuilder::new().operator('&', 60).build("2 & 3 + 1")?;
+/// let expr = parser.parse()?.body.unwrap();
+/// assert_eq!(expr.to_string(), "((2 & 3) + 1)");
+/// # Ok::<(), kaleidoscope::error::Error>(())
+/// ```
+#[derive(Debug, Clone)]
+pub struct ParserBuilder {
+    prec: HashMap<Operator, Fixity>,
+}
+
+impl Default for ParserBuilder {
+    fn default() -> Self {
+        Self {
+            prec: DEFAULT_PRECEDENCE.iter().copied().collect(),
+        }
+    }
+}
+
+impl ParserBuilder {
+    /// Creates a new `ParserBuilder` knowing the default binary operators.
+    pub fn new() -> Self { Self::default() }
+
+    /// Makes `op` a left-associative binary operator with the given
+    /// precedence. Setting the precedence of an operator that is already
+    /// known, including a default one, replaces it.
+    #[must_use]
+    pub fn operator(self, op: impl Into<Operator>, prec: i32) -> Self {
+        self.operator_with_assoc(op, prec, Assoc::Left)
+    }
+
+    /// Makes `op` a binary operator with the given precedence and
+    /// associativity, replacing any previous declaration of it.
+    #[must_use]
+    pub fn operator_with_assoc(mut self, op: impl Into<Operator>, prec: i32, assoc: Assoc) -> Self {
+        self.prec.insert(op.into(), (prec, assoc));
+        self
+    }
+
+    /// Creates a `Parser` over the tokens of `input`, or returns the first
+    /// lexing error. Operators declared with `binary` while parsing are only
+    /// added to that parser's table.
+    pub fn build(&self, input: impl AsRef<str>) -> Result<Parser<'static>> {
+        Parser::with_precedence(input, Precedence::Owned(self.prec.clone()))
+    }
+}
+
+/// The precedence table of a `Parser`, which may be shared with its caller.
+enum Precedence<'a> {
+    Borrowed(&'a mut HashMap<Operator, Fixity>),
+    Owned(HashMap<Operator, Fixity>),
+}
+
+impl Deref for Precedence<'_> {
+    type Target = HashMap<Operator, Fixity>;
+
+    fn deref(&self) -> &Self::Target {
+        match self {
+            Self::Borrowed(prec) => prec,
+            Self::Owned(prec) => prec,
+        }
+    }
+}
+
+impl DerefMut for Precedence<'_> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        match self {
+            Self::Borrowed(prec) => prec,
+            Self::Owned(prec) => prec,
+        }
+    }
+}
+
 pub struct Parser<'a> {
-    tokens: Vec<Token>,
+    tokens:    Vec<Token>,
+    /// The span of each token in `tokens`, at the same index.
+    spans:     Vec<Span>,
+    /// The 1-based line and column of each token in `tokens`, followed by
+    /// those of the end of the input.
+    positions: Vec<(usize, usize)>,
     /// The current position of the token the parser is looking at.
-    pos:    usize,
+    pos:       usize,
     /// Holds the precedence for each binary operator.
-    prec:   &'a mut HashMap<char, i32>,
+    prec:      Precedence<'a>,
+    /// Maps the position of each `def`/`extern` token to the text of the
+    /// comments directly above it.
+    docs:      HashMap<usize, String>,
+    /// Maps the position of a token to the comment following it on the same
+    /// line.
+    trailing:  HashMap<usize, String>,
+    /// Whether an operator missing from `prec` in binary position is an error
+    /// rather than the end of the expression.
+    strict:    bool,
+    /// The input the tokens were lexed from, when built with `with_source`.
+    source:    Option<&'a str>,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(input: impl AsRef<str>, prec: &'a mut HashMap<char, i32>) -> Self {
-        let mut lexer = Lexer::new(input.as_ref());
-        let tokens = lexer.by_ref().collect();
+    /// Creates a new `Parser` over the tokens of `input`, or returns the first
+    /// lexing error.
+    pub fn new(input: impl AsRef<str>, prec: &'a mut HashMap<Operator, Fixity>) -> Result<Self> {
+        Self::with_precedence(input, Precedence::Borrowed(prec))
+    }
 
-        Self { tokens, prec, pos: 0 }
+    /// Creates a new `Parser` over the tokens of `input`, with a precedence
+    /// table of its own seeded with `DEFAULT_PRECEDENCE`.
+    pub fn with_default_precedence(input: impl AsRef<str>) -> Result<Parser<'static>> {
+        ParserBuilder::new().build(input)
     }
 
-    /// Parses the content of the parser.
-    pub fn parse(&mut self) -> io::Result<Function> {
-        let result = match self.current()? {
-            Token::Def => self.parse_definition(),
-            Token::Extern => self.parse_extern(),
-            _ => self.parse_toplevel_expr(),
+    fn with_precedence(input: impl AsRef<str>, prec: Precedence<'a>) -> Result<Self> {
+        let mut parser = Self {
+            tokens: vec![],
+            spans: vec![],
+            positions: vec![],
+            prec,
+            docs: HashMap::new(),
+            trailing: HashMap::new(),
+            pos: 0,
+            strict: false,
+            source: None,
         };
+        parser.lex(input.as_ref())?;
+        Ok(parser)
+    }
+
+    /// Replaces the input of the parser with `input`, reusing the memory of
+    /// the previous tokens. Operators declared so far keep their precedence,
+    /// but the source set by `with_source` is forgotten. On a lexing error,
+    /// the parser is left without any input.
+    pub fn reset(&mut self, input: &str) -> Result<()> {
+        self.pos = 0;
+        self.source = None;
+
+        let result = self.lex(input);
+        if result.is_err() {
+            self.clear();
+        }
+        result
+    }
+
+    /// Empties the tokens and the tables derived from them.
+    fn clear(&mut self) {
+        self.tokens.clear();
+        self.spans.clear();
+        self.positions.clear();
+        self.docs.clear();
+        self.trailing.clear();
+    }
+
+    /// Replaces the tokens with those of `input`.
+    fn lex(&mut self, input: &str) -> Result<()> {
+        self.clear();
+
+        let mut lexer = Lexer::new(input);
+
+        // Comments are kept out of the token stream. A comment on the same line
+        // as the token before it trails that token. Otherwise, a run of
+        // comments on consecutive lines documents the `def`/`extern` right
+        // below it; a blank line breaks the run.
+        let mut comments: Vec<String> = vec![];
+
+        loop {
+            let Spanned { value: token, span } = lexer
+                .spanned_token()
+                .map_err(|err| Error::lexer(err.to_string()).with_span(lexer.token_span()))?;
+            if token == Token::EOF {
+                self.positions.push(lexer.token_position());
+                break;
+            }
+
+            if lexer.line_breaks() > 1 {
+                comments.clear();
+            }
+
+            match token {
+                Token::Comment(text) if lexer.line_breaks() == 0 && !self.tokens.is_empty() => {
+                    self.trailing
+                        .insert(self.tokens.len() - 1, text.trim().to_string());
+                },
+                Token::Comment(text) => comments.push(text.trim().to_string()),
+                token => {
+                    if matches!(token, Token::Keyword(Keyword::Def | Keyword::Extern))
+                        && !comments.is_empty()
+                    {
+                        self.docs.insert(self.tokens.len(), comments.join("\n"));
+                    }
+                    comments.clear();
+                    self.tokens.push(token);
+                    self.spans.push(span);
+                    self.positions.push(lexer.token_position());
+                },
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Creates a new `Parser` that keeps a reference to `source`, so errors can
+    /// be shown alongside the code they refer to.
+    pub fn with_source(source: &'a str, prec: &'a mut HashMap<Operator, Fixity>) -> Result<Self> {
+        let mut parser = Self::new(source, prec)?;
+        parser.source = Some(source);
+        Ok(parser)
+    }
+
+    /// Returns the source text, if the parser was built with `with_source`.
+    /// Together with the span of an error, it can be passed to
+    /// `diagnostics::render` to show the offending line.
+    pub const fn source(&self) -> Option<&'a str> { self.source }
+
+    /// Sets whether or not an operator with no known precedence is rejected
+    /// when it appears between two operands. By default, such an operator
+    /// ends the expression, so `a ? b` parses as `a` and leaves `? b` behind.
+    #[must_use]
+    pub const fn strict(mut self, strict: bool) -> Self {
+        self.strict = strict;
+        self
+    }
 
-        match result {
+    /// Parses the content of the parser. Fails with `Error::Empty` if the
+    /// input holds nothing but whitespace and comments.
+    pub fn parse(&mut self) -> Result<Function> {
+        if self.tokens.is_empty() {
+            return Err(Error::Empty);
+        }
+
+        match self.parse_item() {
             Ok(result) => {
                 if !self.is_eof() {
-                    Err(self.log_err(PE::Eof, "Unexpected token after parsed expression."))
+                    Err(self.error(PE::Eof, "Unexpected token after parsed expression."))
                 } else {
                     Ok(result)
                 }
@@ -49,44 +275,186 @@ impl<'a> Parser<'a> {
         }
     }
 
+    /// Parses the next top-level item, skipping past the `;` that may follow
+    /// it. Returns `Ok(None)` once the input is exhausted.
+    pub fn parse_one(&mut self) -> Result<Option<Function>> {
+        // Skip empty statements.
+        while let Ok(Token::Op(';')) = self.current() {
+            self.pos += 1;
+        }
+
+        if self.is_eof() {
+            return Ok(None);
+        }
+
+        let mut item = self.parse_item()?;
+
+        // A new line may start the next item without a `;` in between.
+        if !self.starts_line() {
+            self.check_missing_operator()?;
+        }
+
+        if let Ok(Token::Op(';')) = self.current() {
+            self.pos += 1;
+
+            // A comment may also trail the separator, as in `def f(x) x; # ...`.
+            if item.trailing_comment.is_none() {
+                item.trailing_comment = self.take_trailing_comment();
+            }
+        }
+
+        Ok(Some(item))
+    }
+
+    /// Parses every item of the input, stopping at the first error. Items may
+    /// be separated by `;`, and top-level expressions are wrapped in anonymous
+    /// functions as by `parse`.
+    pub fn parse_all(&mut self) -> Result<Vec<Function>> {
+        let mut items = vec![];
+
+        while let Some(item) = self.parse_one()? {
+            items.push(item);
+        }
+
+        Ok(items)
+    }
+
+    /// Parses every item of the input, recovering from errors instead of
+    /// stopping at the first one: after an error, parsing resumes after the
+    /// next `;` or at the next `def` or `extern`, so a malformed item doesn't
+    /// hide the valid ones after it. Returns the parsed items along with the
+    /// errors.
+    pub fn parse_all_recovering(&mut self) -> (Vec<Function>, Vec<Error>) {
+        let mut items = vec![];
+        let mut errors = vec![];
+
+        loop {
+            let start = self.pos;
+
+            match self.parse_one() {
+                Ok(Some(item)) => items.push(item),
+                Ok(None) => break,
+                Err(err) => {
+                    errors.push(err);
+
+                    // Always make progress, even if the error was raised on
+                    // the first token of the item.
+                    self.pos = self.pos.max(start + 1);
+                    self.skip_to_next_item();
+                },
+            }
+        }
+
+        (items, errors)
+    }
+
+    /// Advances to the next `;`, `def` or `extern`, or to the end of the
+    /// input.
+    fn skip_to_next_item(&mut self) {
+        while let Ok(token) = self.current() {
+            if let Token::Op(';') | Token::Keyword(Keyword::Def | Keyword::Extern) = token {
+                break;
+            }
+            self.pos += 1;
+        }
+    }
+
+    /// Parses a definition, an extern or a top-level expression.
+    fn parse_item(&mut self) -> Result<Function> {
+        match self.current()? {
+            Token::Keyword(Keyword::Def) => self.parse_definition(),
+            Token::Keyword(Keyword::Extern) => self.parse_extern(),
+            _ => self.parse_toplevel_expr(),
+        }
+    }
+
+    /// Removes and returns the comment trailing the last consumed token.
+    fn take_trailing_comment(&mut self) -> Option<String> {
+        let last = self.pos.checked_sub(1)?;
+        self.trailing.remove(&last)
+    }
+
     /// Returns the current `Token`, or an error that
     /// indicates that the end of the file has been unexpectedly reached
-    pub fn current(&self) -> io::Result<Token> {
-        if self.is_eof() {
-            Err(self.log_err(PE::Eof, "Unexpected end of file."))
-        } else {
-            Ok(self.tokens[self.pos].clone())
+    pub fn current(&self) -> Result<Token> {
+        self.tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or_else(|| self.error(PE::Eof, "Unexpected end of file."))
+    }
+
+    /// Returns the `Token` following the current one without advancing, or
+    /// `Token::EOF` if there is none.
+    pub fn peek(&self) -> Token { self.tokens.get(self.pos + 1).cloned().unwrap_or(Token::EOF) }
+
+    /// Returns the span of the current `Token` in the input. At the end of the
+    /// input, this is the empty span right after the last token.
+    pub fn current_span(&self) -> Span {
+        match self.spans.get(self.pos) {
+            Some(&span) => span,
+            None => self
+                .spans
+                .last()
+                .map_or_else(Span::default, |span| Span::new(span.end, span.end)),
+        }
+    }
+
+    /// Returns the span from the start of the token at index `start` to the
+    /// end of the last consumed token.
+    fn span_from(&self, start: usize) -> Span {
+        let first = self
+            .spans
+            .get(start)
+            .copied()
+            .unwrap_or_else(|| self.current_span());
+
+        match self.pos.checked_sub(1).and_then(|last| self.spans.get(last)) {
+            Some(&last) if self.pos > start => first.join(last),
+            _ => Span::new(first.start, first.start),
         }
     }
 
     /// Advances the position, and returns an empty `Result` whose error
     /// indicates that the end of the file has been unexpectedly reached.
     /// This allows to use the `self.advance()?;` syntax.
-    pub fn advance(&mut self) -> io::Result<()> {
+    pub fn advance(&mut self) -> Result<()> {
         self.pos += 1;
 
         (!self.is_eof())
             .then_some(())
-            .ok_or_else(|| self.log_err(PE::Eof, "Unexpected end of file."))
+            .ok_or_else(|| self.error(PE::Eof, "Unexpected end of file."))
     }
 
+    /// Returns the tokens that have not been consumed yet.
+    pub fn remaining(&self) -> &[Token] { self.tokens.get(self.pos..).unwrap_or_default() }
+
     /// Returns a value indicating whether or not the `Parser`
     /// has reached the end of the input.
     pub const fn is_eof(&self) -> bool { self.pos >= self.tokens.len() }
 
+    /// Returns the known binary operators with their precedence and
+    /// associativity, ordered by operator so that listings are stable across
+    /// runs.
+    pub fn operators(&self) -> BTreeMap<Operator, Fixity> {
+        self.prec.iter().map(|(&op, &entry)| (op, entry)).collect()
+    }
+
     /// Returns the precedence of the current `Token`, or -1 if it is not
     /// recognized as a binary operator.
     pub fn tok_precedence(&self) -> i32 {
         match self.current() {
-            Ok(Token::Op(op)) => self.prec.get(&op).copied().unwrap_or(-1),
-            _ => -1,
+            Ok(token) => match token.as_operator() {
+                Some(op) => OperatorInfo::lookup(&self.prec, op).prec,
+                None => -1,
+            },
+            Err(_) => -1,
         }
     }
 
     /// Parses any expression.
     ///
     /// expression ::= primary binoprhs
-    pub fn parse_expr(&mut self) -> io::Result<Expr> {
+    pub fn parse_expr(&mut self) -> Result<Expr> {
         match self.parse_unary_expr() {
             Ok(lhs) => self.parse_bin_expr(0, lhs),
             err => err,
@@ -96,141 +464,427 @@ impl<'a> Parser<'a> {
     /// Parses a literal number.
     ///
     /// numberexpr ::= number
-    pub fn parse_num_expr(&mut self) -> io::Result<Expr> {
+    pub fn parse_num_expr(&mut self) -> Result<Expr> {
         if let Token::Number(value) = self.current()? {
-            self.advance()?;
-            Ok(Expr::Number(value))
+            let span = self.current_span();
+            // The number may be the last token of the input.
+            let _ = self.advance();
+            Ok(Expr::Number(value, span))
         } else {
-            Err(self.log_err(PE::Syntax, "expected number literal."))
+            Err(self.error(PE::Syntax, "expected number literal."))
         }
     }
 
-    /// Parses an expression enclosed in parenthesis.
+    /// Parses an expression enclosed in parenthesis, or a tuple if the first
+    /// sub-expression is followed by a comma.
     ///
-    /// parenexpr ::= '(' expression ')'
-    pub fn parse_paren_expr(&mut self) -> io::Result<Expr> {
+    /// parenexpr ::= '(' expression ')' ::= tupleexpr
+    pub fn parse_paren_expr(&mut self) -> Result<Expr> {
         match self.current()? {
             Token::LParen => (),
             _ => {
-                return Err(self.log_err(
+                return Err(self.error(
                     PE::Syntax,
                     "Expected '(' character at start of parenthesized expression.",
                 ));
             },
         }
 
+        let start = self.pos;
         self.advance()?;
 
         let expr = self.parse_expr()?;
 
         match self.current()? {
             Token::RParen => (),
+            Token::Comma => return self.parse_tuple_expr(start, expr),
             _ => {
-                return Err(self.log_err(
+                self.check_missing_operator()?;
+                return Err(self.error(
                     PE::Syntax,
                     "Expected ')' character at end of parenthesized expression.",
                 ));
             },
         }
 
-        self.advance()?;
+        // The closing parenthesis may be the last token of the input.
+        let _ = self.advance();
 
         Ok(expr)
     }
 
+    /// Parses the remaining elements of a tuple, given its first element and
+    /// the index of its opening parenthesis. The current token is the comma
+    /// following the first element.
+    ///
+    /// tupleexpr ::= '(' expression (',' expression)+ ')'
+    fn parse_tuple_expr(&mut self, start: usize, first: Expr) -> Result<Expr> {
+        let mut elems = vec![first];
+
+        loop {
+            match self.current()? {
+                Token::Comma => self.advance()?,
+                Token::RParen => break,
+                _ => {
+                    self.check_missing_operator()?;
+                    return Err(
+                        self.error(PE::Syntax, "Expected ',' or ')' character in tuple expression.")
+                    );
+                },
+            }
+
+            elems.push(self.parse_expr()?);
+        }
+
+        let _ = self.advance();
+
+        Ok(Expr::Tuple(elems, self.span_from(start)))
+    }
+
+    /// Parses a block of statements separated by `;`, which may also end the
+    /// last one.
+    ///
+    /// doexpr ::= 'do' '{' (expression (';' expression)* ';'?)? '}'
+    fn parse_do_expr(&mut self) -> Result<Expr> {
+        let start = self.pos;
+
+        // Eat 'do'.
+        self.advance()?;
+
+        match self.current()? {
+            Token::LBrace => self.advance()?,
+            _ => return Err(self.error(PE::Syntax, "Expected '{' after 'do'.")),
+        }
+
+        let mut stmts = vec![];
+
+        loop {
+            if let Token::RBrace = self.current()? {
+                break;
+            }
+
+            stmts.push(self.parse_expr()?);
+
+            match self.current()? {
+                Token::Op(';') => self.advance()?,
+                Token::RBrace => break,
+                _ => {
+                    self.check_missing_operator()?;
+                    return Err(self.error(PE::Syntax, "Expected ';' or '}' in do block."));
+                },
+            }
+        }
+
+        // The closing brace may be the last token of the input.
+        let _ = self.advance();
+
+        Ok(Expr::Do(stmts, self.span_from(start)))
+    }
+
+    /// Parses a conditional expression.
+    ///
+    /// ifexpr ::= 'if' expression 'then' expression 'else' expression
+    fn parse_if_expr(&mut self) -> Result<Expr> {
+        let start = self.pos;
+
+        // Eat 'if'.
+        self.advance()?;
+
+        let cond = self.parse_expr()?;
+
+        // Report a missing keyword even at the end of the input.
+        match self.current() {
+            Ok(Token::Keyword(Keyword::Then)) => self.advance()?,
+            _ => return Err(self.error(PE::Syntax, "Expected 'then' after the condition of 'if'.")),
+        }
+
+        let then = self.parse_expr()?;
+
+        match self.current() {
+            Ok(Token::Keyword(Keyword::Else)) => self.advance()?,
+            _ => return Err(self.error(PE::Syntax, "Expected 'else' after the 'then' branch of 'if'.")),
+        }
+
+        let alt = self.parse_expr()?;
+
+        Ok(Expr::If {
+            cond: Box::new(cond),
+            then: Box::new(then),
+            alt:  Box::new(alt),
+            span: self.span_from(start),
+        })
+    }
+
+    /// Parses a loop expression, whose step is optional.
+    ///
+    /// forexpr ::= 'for' identifier '=' expr ',' expr (',' expr)? 'in' expr
+    fn parse_for_expr(&mut self) -> Result<Expr> {
+        let start_pos = self.pos;
+
+        // Eat 'for'.
+        self.advance()?;
+
+        let var = match self.current()? {
+            Token::Ident(var) => var,
+            _ => return Err(self.error(PE::Syntax, "Expected identifier after 'for'.")),
+        };
+
+        self.advance()?;
+
+        match self.current()? {
+            Token::Op('=') => self.advance()?,
+            _ => return Err(self.error(PE::Syntax, "Expected '=' after the loop variable of 'for'.")),
+        }
+
+        let start = self.parse_expr()?;
+
+        match self.current()? {
+            Token::Comma => self.advance()?,
+            _ => return Err(self.error(PE::Syntax, "Expected ',' after the start value of 'for'.")),
+        }
+
+        let end = self.parse_expr()?;
+
+        let step = match self.current() {
+            Ok(Token::Comma) => {
+                self.advance()?;
+                Some(Box::new(self.parse_expr()?))
+            },
+            _ => None,
+        };
+
+        // Report a missing keyword even at the end of the input.
+        match self.current() {
+            Ok(Token::Keyword(Keyword::In)) => self.advance()?,
+            _ => return Err(self.error(PE::Syntax, "Expected 'in' after the header of 'for'.")),
+        }
+
+        let body = self.parse_expr()?;
+
+        Ok(Expr::For {
+            var,
+            start: Box::new(start),
+            end: Box::new(end),
+            step,
+            body: Box::new(body),
+            span: self.span_from(start_pos),
+        })
+    }
+
+    /// Parses a mutable variable declaration, whose initializers are optional.
+    ///
+    /// varexpr ::= 'var' identifier ('=' expr)? (',' identifier ('=' expr)?)*
+    /// 'in' expr
+    fn parse_var_expr(&mut self) -> Result<Expr> {
+        let start = self.pos;
+
+        // Eat 'var'.
+        self.advance()?;
+
+        let mut bindings = vec![];
+
+        loop {
+            let name = match self.current()? {
+                Token::Ident(name) => name,
+                _ => return Err(self.error(PE::Syntax, "Expected identifier in 'var' binding.")),
+            };
+
+            // A missing 'in' is reported below, even at the end of the input.
+            let _ = self.advance();
+
+            let init = match self.current() {
+                Ok(Token::Op('=')) => {
+                    self.advance()?;
+                    Some(self.parse_expr()?)
+                },
+                _ => None,
+            };
+
+            bindings.push((name, init));
+
+            match self.current() {
+                Ok(Token::Comma) => self.advance()?,
+                _ => break,
+            }
+        }
+
+        // Report a missing keyword even at the end of the input.
+        match self.current() {
+            Ok(Token::Keyword(Keyword::In)) => self.advance()?,
+            _ => return Err(self.error(PE::Syntax, "Expected 'in' after the bindings of 'var'.")),
+        }
+
+        let body = self.parse_expr()?;
+
+        Ok(Expr::Var {
+            bindings,
+            body: Box::new(body),
+            span: self.span_from(start),
+        })
+    }
+
     /// Parses an expression that starts with an identifier (either a variable
     /// or a function call).
     ///
-    /// identifierexpr ::= identifier ::= identifier '(' expression* ')'
-    pub fn parse_ident_expr(&mut self) -> io::Result<Expr> {
+    /// identifierexpr ::= identifier ::= identifier '(' argument* ')'
+    pub fn parse_ident_expr(&mut self) -> Result<Expr> {
         let ident = if let Token::Ident(id) = &self.current()? {
             id.clone()
         } else {
-            return Err(self.log_err(PE::Syntax, "Expected identifier"));
+            return Err(self.error(PE::Syntax, "Expected identifier"));
         };
 
-        // Simple variable ref
-        if self.advance().is_err() {
-            return Ok(Expr::Variable(ident));
-        }
+        let start = self.pos;
 
-        match self.current()? {
+        match self.peek() {
             Token::LParen => {
+                self.advance()?;
                 self.advance()?;
                 if let Token::RParen = self.current()? {
+                    let _ = self.advance();
                     return Ok(Expr::Call {
                         name: ident,
                         args: vec![],
+                        span: self.span_from(start),
                     });
                 }
 
                 let mut args = vec![];
 
                 loop {
-                    args.push(self.parse_expr()?);
+                    let arg = self.parse_call_arg()?;
+
+                    // Named arguments may only follow positional ones.
+                    if !matches!(arg, Expr::NamedArg { .. })
+                        && matches!(args.last(), Some(Expr::NamedArg { .. }))
+                    {
+                        return Err(self.error(
+                            PE::Syntax,
+                            "Positional argument after named argument in function call.",
+                        ));
+                    }
+
+                    args.push(arg);
 
                     match self.current()? {
                         Token::Comma => (),
                         Token::RParen => break,
                         _ => {
-                            return Err(
-                                self.log_err(PE::Syntax, "Expected ',' character in function call.")
-                            );
+                            self.check_missing_operator()?;
+                            return Err(self.error(PE::Syntax, "Expected ',' character in function call."));
                         },
                     }
 
                     self.advance()?;
                 }
 
-                self.advance()?;
+                // The closing parenthesis may be the last token of the input.
+                let _ = self.advance();
 
-                Ok(Expr::Call { name: ident, args })
+                Ok(Expr::Call {
+                    name: ident,
+                    args,
+                    span: self.span_from(start),
+                })
+            },
+
+            // Simple variable ref, which may be the last token of the input.
+            _ => {
+                let _ = self.advance();
+                Ok(Expr::Variable(ident, self.span_from(start)))
             },
+        }
+    }
 
-            _ => Ok(Expr::Variable(ident)),
+    /// Parses a single argument of a function call, which may be named.
+    ///
+    /// An argument starting with `name =` is always named, so it shadows an
+    /// assignment to the variable `name`: `f(x = 1)` passes `1` as the
+    /// parameter `x` of `f`. Parenthesizing the assignment, as in
+    /// `f((x = 1))`, passes its value instead.
+    ///
+    /// argument ::= expression ::= identifier '=' expression
+    fn parse_call_arg(&mut self) -> Result<Expr> {
+        if let (Some(Token::Ident(name)), Some(Token::Op('='))) =
+            (self.tokens.get(self.pos), self.tokens.get(self.pos + 1))
+        {
+            let name = name.clone();
+            let start = self.pos;
+            self.pos += 2;
+
+            let value = self.parse_expr()?;
+            return Ok(Expr::NamedArg {
+                name,
+                value: Box::new(value),
+                span: self.span_from(start),
+            });
         }
+
+        self.parse_expr()
     }
 
     /// Parses a primary expression (an identifier, a number or a parenthesized
     /// expression).
     ///
-    /// primary ::= identifierexpr ::= numberexpr ::= parenexpr
-    pub fn parse_primary(&mut self) -> io::Result<Expr> {
+    /// primary
+    ///   ::= identifierexpr
+    ///   ::= numberexpr
+    ///   ::= parenexpr
+    ///   ::= doexpr
+    ///   ::= ifexpr
+    ///   ::= forexpr
+    ///   ::= varexpr
+    pub fn parse_primary(&mut self) -> Result<Expr> {
         match self.current()? {
             Token::Ident(_) => self.parse_ident_expr(),
             Token::Number(_) => self.parse_num_expr(),
             Token::LParen => self.parse_paren_expr(),
-            _ => Err(self.log_err(PE::Syntax, "unknown token when expecting an expression")),
+            Token::Keyword(Keyword::Do) => self.parse_do_expr(),
+            Token::Keyword(Keyword::If) => self.parse_if_expr(),
+            Token::Keyword(Keyword::For) => self.parse_for_expr(),
+            Token::Keyword(Keyword::Var) => self.parse_var_expr(),
+            _ => Err(self.error(PE::Syntax, "unknown token when expecting an expression")),
         }
     }
 
-    /// Parses an unary expression.
-    pub fn parse_unary_expr(&mut self) -> io::Result<Expr> {
+    /// Parses an unary expression. A `-` applied directly to a number literal
+    /// is folded into a negative `Number`, rather than calling `unary-`.
+    pub fn parse_unary_expr(&mut self) -> Result<Expr> {
         match self.current()? {
             Token::Op(op) => {
+                let start = self.pos;
                 self.advance()?;
 
-                let name = format!("unary{op}");
-                Ok(Expr::Call {
-                    name,
-                    args: vec![self.parse_unary_expr()?],
-                })
+                let operand = self.parse_unary_expr()?;
+                let span = self.span_from(start);
+
+                match (op, operand) {
+                    ('-', Expr::Number(value, _)) => Ok(Expr::Number(-value, span)),
+                    (op, operand) => Ok(Expr::Call {
+                        name: format!("unary{op}"),
+                        args: vec![operand],
+                        span,
+                    }),
+                }
             },
             _ => self.parse_primary(),
         }
     }
 
     /// Parses a binary expression, given its left-hand expression.
-    pub fn parse_bin_expr(&mut self, prec: i32, mut lhs: Expr) -> io::Result<Expr> {
+    pub fn parse_bin_expr(&mut self, prec: i32, mut lhs: Expr) -> Result<Expr> {
         loop {
             let curr_prec = self.tok_precedence();
             if curr_prec < prec || self.is_eof() {
+                if self.strict && curr_prec < 0 {
+                    self.check_known_operator()?;
+                }
                 return Ok(lhs);
             }
 
-            let op = match self.current()? {
-                Token::Op(op) => op,
-                _ => return Err(self.log_err(PE::Syntax, "Invalid operator.")),
+            let op = match self.current()?.as_operator() {
+                Some(op) => op,
+                None => return Err(self.error(PE::Syntax, "Invalid operator.")),
             };
 
             self.advance()?;
@@ -238,45 +892,113 @@ impl<'a> Parser<'a> {
             // If BinOp binds less tightly with RHS than the operator after RHS, let
             // the pending operator take RHS as its LHS.
             let mut rhs = self.parse_unary_expr()?;
-            let next_prec = self.tok_precedence();
+            let curr = OperatorInfo::lookup(&self.prec, op);
 
-            if curr_prec < next_prec {
-                rhs = self.parse_bin_expr(curr_prec + 1, rhs)?;
+            if let Some(next) = self.current().ok().and_then(|token| token.as_operator())
+                && OperatorInfo::lookup(&self.prec, next).binds_tighter_than(&curr)
+            {
+                rhs = self.parse_bin_expr(curr.rhs_min_prec(), rhs)?;
             }
 
             // Merge LHS/RHS.
-            lhs = Expr::Binary {
-                op,
-                lhs: Box::new(lhs),
-                rhs: Box::new(rhs),
+            let span = lhs.span().join(rhs.span());
+            lhs = match (op.as_char(), lhs) {
+                // Chained `:` operators make up a single sequence.
+                (Some(':'), Expr::Seq(mut exprs, _)) => {
+                    exprs.push(rhs);
+                    Expr::Seq(exprs, span)
+                },
+                (Some(':'), lhs) => Expr::Seq(vec![lhs, rhs], span),
+                (_, lhs) => Expr::Binary {
+                    op,
+                    lhs: Box::new(lhs),
+                    rhs: Box::new(rhs),
+                    span,
+                },
             };
         }
     }
 
-    /// prototype  ::= id '(' id* ')'
-    pub fn parse_prototype(&mut self) -> io::Result<Prototype> {
-        let (id, is_operator, precedence) = match self.current()? {
+    /// Returns an error if the current token starts an expression, which means
+    /// it directly follows another one, as in `a b` or `1 (2)`. This is almost
+    /// always a forgotten operator, so it gets its own message.
+    fn check_missing_operator(&self) -> Result<()> {
+        let starts_expr = matches!(
+            self.current(),
+            Ok(Token::Ident(_)
+                | Token::Number(_)
+                | Token::LParen
+                | Token::Keyword(Keyword::Do | Keyword::For | Keyword::If | Keyword::Var))
+        );
+
+        match self.pos.checked_sub(1).and_then(|prev| self.tokens.get(prev)) {
+            Some(prev) if starts_expr => Err(self.error(
+                PE::Syntax,
+                &format!(
+                    "Expected an operator between `{prev}` and `{}`.",
+                    self.tokens[self.pos]
+                ),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Whether the current token is the first on its line.
+    fn starts_line(&self) -> bool {
+        match (self.pos.checked_sub(1), self.positions.get(self.pos)) {
+            (Some(prev), Some((line, _))) => self.positions[prev].0 < *line,
+            _ => true,
+        }
+    }
+
+    /// Returns an error if the current token is an operator with no known
+    /// precedence. `;` is a statement separator, not an operator.
+    fn check_known_operator(&self) -> Result<()> {
+        match self.current().ok().and_then(|token| token.as_operator()) {
+            Some(op) if op != ';' && !self.prec.contains_key(&op) => {
+                Err(self.error(PE::Syntax, &format!("Unknown operator `{op}`.")))
+            },
+            _ => Ok(()),
+        }
+    }
+
+    /// prototype
+    ///   ::= id '(' id* ')'
+    ///   ::= 'binary' op 'right'? number? '(' id* ')'
+    ///   ::= 'unary' op '(' id ')'
+    pub fn parse_prototype(&mut self) -> Result<Prototype> {
+        const EXPECTED_OP: &str = "Expected operator in custom operator declaration.";
+        const UNARY_ARITY: &str = "Expected exactly one parameter in unary operator declaration.";
+
+        let start = self.pos;
+
+        let (id, is_operator, precedence, assoc) = match self.current()? {
             Token::Ident(id) => {
                 self.advance()?;
-                (id, false, 0)
+                (id, false, 0, Assoc::Left)
             },
 
-            Token::Binary => {
+            Token::Keyword(Keyword::Binary) => {
                 self.advance()?;
 
-                let op = match self.current()? {
-                    Token::Op(ch) => ch,
-                    _ => {
-                        return Err(
-                            self.log_err(PE::Syntax, "Expected operator in custom operator declaration.")
-                        );
-                    },
+                let op = match self.current()?.as_operator() {
+                    Some(op) => op,
+                    None => return Err(self.error(PE::Syntax, EXPECTED_OP)),
                 };
 
                 self.advance()?;
 
                 let name = format!("binary{op}");
 
+                // `right` is only special here, so it stays a valid identifier.
+                let assoc = match self.current()? {
+                    Token::Ident(word) if word == "right" => {
+                        self.advance()?;
+                        Assoc::Right
+                    },
+                    _ => Assoc::Left,
+                };
+
                 let prec = if let Token::Number(prec) = self.current()? {
                     self.advance()?;
                     prec as usize
@@ -284,29 +1006,53 @@ impl<'a> Parser<'a> {
                     0
                 };
 
-                self.prec.insert(op, prec as i32);
+                self.prec.insert(op, (prec as i32, assoc));
 
-                (name, true, prec)
+                (name, true, prec, assoc)
             },
 
-            _ => return Err(self.log_err(PE::Syntax, "Expected identifier in prototype declaration.")),
+            Token::Keyword(Keyword::Unary) => {
+                self.advance()?;
+
+                let op = match self.current()? {
+                    Token::Op(ch) => ch,
+                    _ => return Err(self.error(PE::Syntax, EXPECTED_OP)),
+                };
+
+                self.advance()?;
+
+                // `parse_unary_expr` already turns `!x` into a call to `unary!`.
+                (format!("unary{op}"), true, 0, Assoc::Left)
+            },
+
+            _ => return Err(self.error(PE::Syntax, "Expected identifier in prototype declaration.")),
         };
 
         match self.current()? {
             Token::LParen => (),
-            _ => return Err(self.log_err(PE::Syntax, "Expected '(' character in prototype declaration.")),
+            _ => return Err(self.error(PE::Syntax, "Expected '(' character in prototype declaration.")),
         }
 
         self.advance()?;
 
+        let is_unary = is_operator && id.starts_with("unary");
+
         if let Token::RParen = self.current()? {
+            if is_unary {
+                return Err(self.error(PE::Syntax, UNARY_ARITY));
+            }
+
             self.advance()?;
 
             return Ok(Prototype {
-                name:  id,
-                args:  vec![],
+                name: id,
+                args: vec![],
                 is_op: is_operator,
-                prec:  precedence,
+                prec: precedence,
+                assoc,
+                trailing_comment: self.take_trailing_comment(),
+                abi: None,
+                span: self.span_from(start),
             });
         }
 
@@ -315,7 +1061,7 @@ impl<'a> Parser<'a> {
         loop {
             match self.current()? {
                 Token::Ident(name) => args.push(name),
-                _ => return Err(self.log_err(PE::Syntax, "Expected identifier in parameter declaration.")),
+                _ => return Err(self.error(PE::Syntax, "Expected identifier in parameter declaration.")),
             }
 
             self.advance()?;
@@ -329,7 +1075,7 @@ impl<'a> Parser<'a> {
                     let _ = self.advance();
                 },
                 _ => {
-                    return Err(self.log_err(
+                    return Err(self.error(
                         PE::Syntax,
                         "Expected ',' or ')' character in prototype declaration.",
                     ));
@@ -337,71 +1083,659 @@ impl<'a> Parser<'a> {
             }
         }
 
+        if is_unary && args.len() != 1 {
+            return Err(self.error(PE::Syntax, UNARY_ARITY));
+        }
+
         Ok(Prototype {
             name: id,
             args,
             is_op: is_operator,
             prec: precedence,
+            assoc,
+            abi: None,
+            trailing_comment: self.take_trailing_comment(),
+            span: self.span_from(start),
         })
     }
 
-    /// definition ::= 'def' prototype expression
-    pub fn parse_definition(&mut self) -> io::Result<Function> {
+    /// Parses a function definition, or a forward declaration if the prototype
+    /// is directly followed by `;`. A prototype at the end of the input is an
+    /// error, so a missing body is not mistaken for a declaration.
+    ///
+    /// definition ::= 'def' prototype expression ::= 'def' prototype ';'
+    pub fn parse_definition(&mut self) -> Result<Function> {
+        let doc = self.docs.remove(&self.pos);
+        let start = self.pos;
+
         // Eat 'def' keyword
         self.pos += 1;
 
         // Parse signature of function
         let proto = self.parse_prototype()?;
 
-        // Parse function body
-        let body = self.parse_expr()?;
+        // Parse function body, unless this is a forward declaration
+        let body = match self.current() {
+            Ok(Token::Op(';')) => None,
+            Ok(_) => Some(self.parse_expr()?),
+            Err(_) => {
+                return Err(self.error(PE::Eof, "Expected a function body or ';' after the prototype."));
+            },
+        };
 
         // Return new function
         Ok(Function {
             proto,
-            body: Some(body),
+            body,
             is_anon: false,
+            is_extern: false,
+            doc,
+            trailing_comment: self.take_trailing_comment(),
+            span: self.span_from(start),
         })
     }
 
-    /// Parses an external function declaration.
+    /// Parses an external function declaration, optionally annotated with the
+    /// name of its calling convention.
     ///
-    /// external ::= 'extern' prototype
-    pub fn parse_extern(&mut self) -> io::Result<Function> {
+    /// external ::= 'extern' string? prototype
+    pub fn parse_extern(&mut self) -> Result<Function> {
+        let doc = self.docs.remove(&self.pos);
+        let start = self.pos;
+
         // Eat 'extern' keyword
         self.pos += 1;
 
+        let abi = match self.current()? {
+            Token::Str(abi) => {
+                self.advance()?;
+                Some(abi)
+            },
+            _ => None,
+        };
+
         // Parse signature of extern function
-        let proto = self.parse_prototype()?;
+        let mut proto = self.parse_prototype()?;
+        proto.abi = abi;
+
+        // The prototype is the whole item, so the item keeps its comment.
+        let trailing_comment = proto.trailing_comment.take();
+
         Ok(Function {
             proto,
             body: None,
             is_anon: false,
+            is_extern: true,
+            doc,
+            trailing_comment,
+            span: self.span_from(start),
         })
     }
 
     /// toplevelexpr ::= expression
-    pub fn parse_toplevel_expr(&mut self) -> io::Result<Function> {
+    pub fn parse_toplevel_expr(&mut self) -> Result<Function> {
         match self.parse_expr() {
-            Ok(value) => Ok(Function {
-                proto:   Prototype {
-                    name:  FUNC_NAME.to_string(),
-                    args:  vec![],
-                    prec:  0,
-                    is_op: false,
-                },
-                body:    Some(value),
-                is_anon: true,
-            }),
+            Ok(value) => {
+                // The prototype is implicit, so it's empty.
+                let span = value.span();
+                Ok(Function {
+                    proto: Prototype {
+                        name:             FUNC_NAME.to_string(),
+                        args:             vec![],
+                        prec:             0,
+                        assoc:            Assoc::Left,
+                        is_op:            false,
+                        abi:              None,
+                        trailing_comment: None,
+                        span:             Span::new(span.start, span.start),
+                    },
+                    body: Some(value),
+                    is_anon: true,
+                    is_extern: false,
+                    doc: None,
+                    trailing_comment: self.take_trailing_comment(),
+                    span,
+                })
+            },
             Err(value) => Err(value),
         }
     }
 
-    fn log_err(&self, kind: PE, error: &str) -> io::Error {
-        let kind = match kind {
-            PE::Syntax => io::ErrorKind::InvalidData,
-            PE::Eof => io::ErrorKind::UnexpectedEof,
+    /// Creates a parse error pointing at the current token. Syntax errors
+    /// name the token, unless `message` already does.
+    fn error(&self, kind: PE, message: &str) -> Error {
+        let message = match (kind, self.tokens.get(self.pos)) {
+            (PE::Syntax, Some(token)) if !message.contains(&format!("`{token}`")) => {
+                format!("{message} Found `{token}`.")
+            },
+            _ => message.to_string(),
+        };
+
+        Error::Parse {
+            kind,
+            message,
+            span: Some(self.current_span()),
+            position: self.positions.get(self.pos).or(self.positions.last()).copied(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostics;
+    use crate::testing::{parse_expr, parse_items};
+
+    #[test]
+    fn parses_tuples() {
+        assert!(matches!(parse_expr("(1, 2)"), Expr::Tuple(elems, _) if elems.len() == 2));
+        assert!(matches!(parse_expr("(1)"), Expr::Number(1.0, _)));
+    }
+
+    #[test]
+    fn current_past_the_end_is_an_error() {
+        let mut prec = HashMap::new();
+        let mut parser = Parser::new("1", &mut prec).unwrap();
+        parser.pos = 5;
+        assert!(parser.current().is_err());
+    }
+
+    #[test]
+    fn parses_extern_abi() {
+        let items = parse_items(r#"extern "C" sin(x); extern cos(x)"#);
+        assert_eq!(items[0].proto.abi.as_deref(), Some("C"));
+        assert_eq!(items[1].proto.abi, None);
+    }
+
+    #[test]
+    fn exposes_remaining_tokens() {
+        let mut prec = HashMap::from_iter(DEFAULT_PRECEDENCE.iter().copied());
+        let mut parser = Parser::new("1 + 2 )", &mut prec).unwrap();
+        parser.parse_expr().unwrap();
+        assert_eq!(parser.remaining(), [Token::RParen]);
+    }
+
+    #[test]
+    fn parses_one_item_at_a_time() {
+        let mut parser = Parser::with_default_precedence("def f(x) x; 1 + 2").unwrap();
+
+        let def = parser.parse_one().unwrap().unwrap();
+        assert_eq!(def.proto.name, "f");
+        assert!(!def.is_anon);
+
+        let expr = parser.parse_one().unwrap().unwrap();
+        assert!(expr.is_anon);
+
+        assert!(parser.parse_one().unwrap().is_none());
+    }
+
+    #[test]
+    fn remainder_binds_like_multiplication() {
+        assert_eq!(parse_expr("1 + 7 % 3 * 2").to_string(), "(1 + ((7 % 3) * 2))");
+    }
+
+    #[test]
+    fn greater_than_binds_like_less_than() {
+        assert_eq!(parse_expr("a + 1 > b < c").to_string(), "(((a + 1) > b) < c)");
+    }
+
+    #[test]
+    fn strict_mode_rejects_unknown_operators() {
+        let mut lenient = Parser::with_default_precedence("a ? b").unwrap();
+        assert!(matches!(lenient.parse_expr().unwrap(), Expr::Variable(name, _) if name == "a"));
+        assert_eq!(lenient.remaining().len(), 2);
+
+        let mut strict = Parser::with_default_precedence("a ? b").unwrap().strict(true);
+        assert!(strict.parse_expr().is_err());
+    }
+
+    #[test]
+    fn source_renders_the_offending_line() {
+        let source = "def f(x)\n  x y";
+        let mut prec = DEFAULT_PRECEDENCE.iter().copied().collect();
+        let mut parser = Parser::with_source(source, &mut prec).unwrap();
+
+        let err = parser.parse_one().unwrap_err();
+        let rendered = diagnostics::render(parser.source().unwrap(), err.span().unwrap(), &err.message());
+        assert!(rendered.contains("2 |   x y"), "{rendered}");
+    }
+
+    #[test]
+    fn parses_forward_declarations() {
+        let items = parse_items("def f(x); def f(x) x + 1");
+        assert!(items[0].body.is_none() && !items[0].is_extern);
+        assert!(items[1].body.is_some());
+
+        let mut parser = Parser::with_default_precedence("def f(x)").unwrap();
+        assert!(parser.parse_one().is_err());
+    }
+
+    #[test]
+    fn operators_are_listed_in_order() {
+        let listing = || {
+            let mut parser = Parser::with_default_precedence("def binary| 5 (a, b) a").unwrap();
+            parser.parse_one().unwrap();
+            parser.operators().into_iter().collect::<Vec<_>>()
+        };
+
+        let first = listing();
+        assert!(first.is_sorted_by_key(|&(op, _)| op));
+        assert_eq!(first, listing());
+    }
+
+    #[test]
+    fn attaches_trailing_comments() {
+        let items = parse_items("# Leading.\ndef f(x) x+1 # the inc function\ndef g(x) x");
+        assert_eq!(items[0].doc.as_deref(), Some("Leading."));
+        assert_eq!(items[0].trailing_comment.as_deref(), Some("the inc function"));
+        assert_eq!(items[1].trailing_comment, None);
+        assert_eq!(items[0].proto.trailing_comment, None);
+    }
+
+    #[test]
+    fn attaches_comments_trailing_the_prototype() {
+        let items = parse_items(
+            "def f(x) # the inc function
+  x+1 # plus one
+extern g(x) # native
+def h(x)
+  x",
+        );
+        assert_eq!(
+            items[0].proto.trailing_comment.as_deref(),
+            Some("the inc function")
+        );
+        assert_eq!(items[0].trailing_comment.as_deref(), Some("plus one"));
+        assert_eq!(items[1].trailing_comment.as_deref(), Some("native"));
+        assert_eq!(items[1].proto.trailing_comment, None);
+        assert_eq!(items[2].proto.trailing_comment, None);
+    }
+
+    #[test]
+    fn recovers_from_a_bad_prototype() {
+        let mut parser = Parser::with_default_precedence("def f(x) x\ndef (y) y\ndef g(z) z").unwrap();
+        let (items, errors) = parser.parse_all_recovering();
+
+        let names: Vec<_> = items.iter().map(|func| func.proto.name.as_str()).collect();
+        assert_eq!(names, ["f", "g"]);
+        assert_eq!(errors.len(), 1);
+    }
+
+    #[test]
+    fn parses_do_blocks() {
+        let Expr::Do(stmts, _) = parse_expr("do { printd(1); printd(2); 42 }") else {
+            panic!("expected a `do` block");
+        };
+        assert_eq!(stmts.len(), 3);
+        assert!(matches!(stmts[2], Expr::Number(42.0, _)));
+
+        assert!(matches!(parse_expr("do {}"), Expr::Do(stmts, _) if stmts.is_empty()));
+    }
+
+    fn parse_error(input: &str) -> Error {
+        Parser::with_default_precedence(input)
+            .and_then(|mut parser| parser.parse_expr())
+            .unwrap_err()
+    }
+
+    #[test]
+    fn parses_nested_if_expressions() {
+        let Expr::If { then, alt, .. } = parse_expr("if a then if b then 1 else 2 else 3") else {
+            panic!("expected an `if` expression");
+        };
+        assert!(matches!(*then, Expr::If { .. }));
+        assert!(matches!(*alt, Expr::Number(3.0, _)));
+    }
+
+    #[test]
+    fn parses_if_as_a_binary_operand() {
+        let expr = parse_expr("1 + (if x < 2 then x else 2)");
+        let (_, _, rhs) = expr.as_binary().unwrap();
+        assert!(matches!(rhs, Expr::If { .. }));
+    }
+
+    #[test]
+    fn rejects_if_without_then_or_else() {
+        for (input, message) in [
+            ("if x 1 else 2", "Expected 'then' after the condition of 'if'."),
+            ("if x then 1", "Expected 'else' after the 'then' branch of 'if'."),
+        ] {
+            let err = parse_error(input);
+            assert!(matches!(err, Error::Parse { kind: PE::Syntax, .. }), "{input}");
+            assert!(err.message().starts_with(message), "{err}");
+        }
+    }
+
+    fn parse_one_error(input: &str) -> Error {
+        Parser::with_default_precedence(input)
+            .and_then(|mut parser| parser.parse_one())
+            .unwrap_err()
+    }
+
+    #[test]
+    fn reports_a_missing_operator_between_expressions() {
+        assert_eq!(
+            parse_one_error("a b").message(),
+            "Expected an operator between `a` and `b`."
+        );
+        assert_eq!(
+            parse_one_error("1 (2)").message(),
+            "Expected an operator between `1` and `(`."
+        );
+        assert_eq!(
+            parse_one_error("def f(x) x 1").message(),
+            "Expected an operator between `x` and `1`."
+        );
+        assert_eq!(
+            parse_one_error("f(1\n2)").message(),
+            "Expected an operator between `1` and `2`."
+        );
+    }
+
+    #[test]
+    fn separates_items_by_new_lines() {
+        let mut parser = Parser::with_default_precedence("def sq(x) x*x\nsq(2)\n1\n2").unwrap();
+        let items = parser.parse_all().unwrap();
+        assert_eq!(items.len(), 4);
+        assert_eq!(items[0].proto.name, "sq");
+    }
+
+    #[test]
+    fn parses_for_loops_with_and_without_a_step() {
+        let Expr::For { var, step, .. } = parse_expr("for i = 1, i < n, 2 in f(i)") else {
+            panic!("expected a `for` loop");
+        };
+        assert_eq!(var, "i");
+        assert!(matches!(step.as_deref(), Some(Expr::Number(2.0, _))));
+
+        assert!(matches!(parse_expr("for i = 1, i < n in f(i)"), Expr::For {
+            step: None,
+            ..
+        }));
+    }
+
+    #[test]
+    fn rejects_for_without_in() {
+        let err = parse_error("for i = 1, i < n f(i)");
+        assert!(
+            err.message()
+                .starts_with("Expected 'in' after the header of 'for'."),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn parses_var_bindings() {
+        let Expr::Var { bindings, .. } = parse_expr("var x = 1 in x") else {
+            panic!("expected a `var` expression");
+        };
+        assert!(matches!(bindings.as_slice(), [(x, Some(Expr::Number(1.0, _)))] if x == "x"));
+
+        let Expr::Var { bindings, .. } = parse_expr("var x = 1, y in x + y") else {
+            panic!("expected a `var` expression");
+        };
+        assert!(matches!(bindings.as_slice(), [(x, Some(_)), (y, None)] if x == "x" && y == "y"));
+    }
+
+    #[test]
+    fn rejects_var_without_in() {
+        let err = parse_error("var x = 1 x");
+        assert!(
+            err.message()
+                .starts_with("Expected 'in' after the bindings of 'var'."),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn defines_and_uses_unary_operators() {
+        let items = parse_items("def unary!(v) if v then 0 else 1; !x");
+        assert_eq!(items[0].proto.name, "unary!");
+        assert!(items[0].proto.is_op);
+        assert_eq!(items[0].proto.args, ["v"]);
+
+        let body = items[1].body.as_ref().unwrap();
+        assert!(matches!(body.as_call(), Some(("unary!", [Expr::Variable(x, _)])) if x == "x"));
+    }
+
+    #[test]
+    fn rejects_unary_operators_without_exactly_one_parameter() {
+        for input in ["def unary!() 0", "def unary!(a, b) a"] {
+            let err = parse_one_error(input);
+            assert!(
+                err.message()
+                    .starts_with("Expected exactly one parameter in unary operator"),
+                "{input}: {err}"
+            );
+        }
+    }
+
+    #[test]
+    fn keeps_a_span_for_every_token() {
+        let mut prec = HashMap::new();
+        let mut parser = Parser::new("foo + 12.5", &mut prec).unwrap();
+
+        let mut spans = vec![];
+        while !parser.is_eof() {
+            spans.push(parser.current_span());
+            parser.pos += 1;
+        }
+
+        assert_eq!(spans, [Span::new(0, 3), Span::new(4, 5), Span::new(6, 10)]);
+        assert_eq!(parser.current_span(), Span::new(10, 10));
+    }
+
+    #[test]
+    fn surfaces_lexer_errors() {
+        let mut prec = HashMap::new();
+        let err = Parser::new("12.x.y", &mut prec).err().unwrap();
+        assert!(matches!(err, Error::Lexer { .. }), "{err}");
+    }
+
+    #[test]
+    fn reports_malformed_prototypes_as_parse_errors() {
+        let err = parse_one_error("def 1(x) x");
+        assert!(matches!(err, Error::Parse { kind: PE::Syntax, .. }), "{err}");
+
+        let err = parse_one_error("def f(x");
+        assert!(matches!(err, Error::Parse { kind: PE::Eof, .. }), "{err}");
+    }
+
+    #[test]
+    fn peeks_at_the_next_token() {
+        let mut prec = HashMap::new();
+        let mut parser = Parser::new("f(x)", &mut prec).unwrap();
+        assert_eq!(parser.peek(), Token::LParen);
+
+        parser.pos = 3;
+        assert_eq!(parser.peek(), Token::EOF);
+    }
+
+    #[test]
+    fn trailing_identifiers_parse_as_variables() {
+        assert!(matches!(parse_expr("x"), Expr::Variable(x, _) if x == "x"));
+
+        let expr = parse_expr("f(x) + y");
+        let (_, lhs, rhs) = expr.as_binary().unwrap();
+        assert!(lhs.as_call().is_some());
+        assert_eq!(rhs.as_variable(), Some("y"));
+    }
+
+    #[test]
+    fn parses_every_item_of_a_program() {
+        let items = parse_items("def one() 1;\nextern sin(x);;\ndef two(x) x * 2;\ntwo(one())");
+        let names: Vec<_> = items.iter().map(|item| item.proto.name.as_str()).collect();
+
+        assert_eq!(items.len(), 4);
+        assert_eq!(names[..3], ["one", "sin", "two"]);
+        assert!(items[1].is_extern);
+        assert!(items[3].is_anon);
+    }
+
+    #[test]
+    fn ignores_comments_inside_expressions() {
+        assert_eq!(parse_expr("1 + # hi\n 2").to_string(), "(1 + 2)");
+    }
+
+    #[test]
+    fn folds_unary_minus_into_literals() {
+        assert!(matches!(parse_expr("-3"), Expr::Number(-3.0, _)));
+        assert!(matches!(parse_expr("-x").as_call(), Some(("unary-", [Expr::Variable(x, _)])) if x == "x"));
+        assert!(matches!(
+            parse_expr("1 - 3").as_binary(),
+            Some((_, _, Expr::Number(3.0, _)))
+        ));
+    }
+
+    #[test]
+    fn reports_the_line_and_column_of_errors() {
+        let err = parse_one_error("def f(x)\n  (x + )");
+        assert!(err.to_string().starts_with("parse error at 2:8: "), "{err}");
+
+        let err = parse_one_error("def f(x\n+");
+        assert_eq!(
+            err.to_string(),
+            "parse error at 2:1: Expected ',' or ')' character in prototype declaration. Found `+`."
+        );
+    }
+
+    #[test]
+    fn parses_with_the_default_precedence() {
+        assert_eq!(parse_expr("1 < 2 + 3").to_string(), "(1 < (2 + 3))");
+        assert_eq!(parse_expr("a - b * c - d").to_string(), "((a - (b * c)) - d)");
+    }
+
+    fn build(builder: &ParserBuilder, input: &str) -> String {
+        builder
+            .build(input)
+            .and_then(|mut parser| parser.parse_expr())
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn builder_registers_operators() {
+        let builder = ParserBuilder::new().operator('^', 60);
+        assert_eq!(build(&builder, "2 ^ 3 + 1"), "((2 ^ 3) + 1)");
+        assert_eq!(build(&builder, "1 + 2 ^ 3"), "(1 + (2 ^ 3))");
+        assert_eq!(build(&builder, "2 ^ 3 ^ 2"), "((2 ^ 3) ^ 2)");
+    }
+
+    #[test]
+    fn later_builder_operators_replace_earlier_ones() {
+        let builder = ParserBuilder::new().operator('^', 60).operator('^', 10);
+        assert_eq!(build(&builder, "2 ^ 3 + 1"), "(2 ^ (3 + 1))");
+    }
+
+    #[test]
+    fn collects_every_parse_error() {
+        let mut parser = Parser::with_default_precedence("def f(x x; def g(y) y; def h(z) (z").unwrap();
+        let (items, errors) = parser.parse_all_recovering();
+
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].proto.name, "g");
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn flattens_chained_sequences() {
+        let Expr::Seq(exprs, _) = parse_expr("printd(1) : printd(2) : 3") else {
+            panic!("expected a sequence");
+        };
+        assert_eq!(exprs.len(), 3);
+        assert!(matches!(exprs[2], Expr::Number(3.0, _)));
+
+        // `:` binds looser than the other operators.
+        assert!(matches!(parse_expr("a : b + c"), Expr::Seq(exprs, _) if exprs[1].as_binary().is_some()));
+    }
+
+    #[test]
+    fn reports_empty_input() {
+        for input in ["", "   ", "# just a comment", "\n#{ a block #}\n"] {
+            let result = Parser::with_default_precedence(input).and_then(|mut parser| parser.parse());
+            assert!(matches!(result, Err(Error::Empty)), "{input:?}");
+        }
+        assert!(
+            Parser::with_default_precedence(" x ")
+                .and_then(|mut parser| parser.parse())
+                .is_ok()
+        );
+    }
+
+    #[test]
+    fn reset_matches_a_fresh_parser() {
+        let mut reused = Parser::with_default_precedence("def binary| 5 (a, b) a; a | b").unwrap();
+        reused.parse_all().unwrap();
+        let capacity = reused.tokens.capacity();
+
+        for input in ["x + 1", "def f(x) x * 2", "f(1, 2)"] {
+            reused.reset(input).unwrap();
+            let fresh = Parser::with_default_precedence(input).unwrap();
+            assert_eq!(reused.tokens, fresh.tokens);
+            assert_eq!(reused.spans, fresh.spans);
+            assert_eq!(reused.pos, 0);
+        }
+        assert_eq!(reused.tokens.capacity(), capacity);
+
+        // Operators declared before the reset are still known.
+        reused.reset("a | b").unwrap();
+        assert!(reused.parse_expr().unwrap().as_binary().is_some());
+
+        assert!(reused.reset("1.2.3").is_err());
+        assert!(reused.tokens.is_empty());
+    }
+
+    #[test]
+    fn assignment_nests_right() {
+        assert_eq!(parse_expr("a = b = c").to_string(), "(a = (b = c))");
+        assert_eq!(parse_expr("a - b - c").to_string(), "((a - b) - c)");
+        assert_eq!(parse_expr("a = b + 1").to_string(), "(a = (b + 1))");
+    }
+
+    #[test]
+    fn user_operators_may_be_right_associative() {
+        let items = parse_items("def binary~ right 5 (a, b) a - b; x ~ y ~ z");
+        assert_eq!(items[0].proto.assoc, Assoc::Right);
+        assert_eq!(items[0].proto.to_string(), "binary~ right 5 (a, b)");
+        assert_eq!(items[1].body.as_ref().unwrap().to_string(), "(x ~ (y ~ z))");
+
+        let builder = ParserBuilder::new().operator_with_assoc('|', 5, Assoc::Right);
+        assert_eq!(build(&builder, "x | y | z"), "(x | (y | z))");
+    }
+
+    #[test]
+    fn power_binds_tighter_than_multiplication_and_nests_right() {
+        assert_eq!(parse_expr("2 * 3 ^ 2").to_string(), "(2 * (3 ^ 2))");
+        assert_eq!(parse_expr("2 ^ 3 * 2").to_string(), "((2 ^ 3) * 2)");
+        assert_eq!(parse_expr("2 ^ 3 ^ 2").to_string(), "(2 ^ (3 ^ 2))");
+    }
+
+    #[test]
+    fn lexer_errors_point_at_the_bad_token() {
+        let input = "def f(x)\n  x + 12.x.y";
+        let err = Parser::with_default_precedence(input).err().unwrap();
+        assert!(matches!(err, Error::Lexer { .. }), "{err:?}");
+        assert_eq!(err.span(), Some(Span::new(15, 18)));
+        assert_eq!(
+            diagnostics::render(input, err.span().unwrap(), &err.message()),
+            "error: Expected a digit after '.' in number literal.\n --> 2:7\n  |\n2 |   x + 12.x.y\n  |       ^^^\n"
+        );
+    }
+
+    #[test]
+    fn named_args_shadow_assignments() {
+        let Expr::Call { args, .. } = parse_expr("f(x = 1)") else {
+            panic!()
+        };
+        assert!(matches!(&args[..], [Expr::NamedArg { name, .. }] if name == "x"));
+
+        let Expr::Call { args, .. } = parse_expr("f((x = 1), y == 2)") else {
+            panic!()
         };
-        io::Error::new(kind, error)
+        assert!(
+            matches!(&args[0], Expr::Binary { op, .. } if op.as_char() == Some('=')),
+            "{args:?}"
+        );
+        assert!(matches!(&args[1], Expr::Binary { op, .. } if *op == Operator::pair('=', '=')));
     }
 }
@@ -0,0 +1,197 @@
+//! Rendering of the AST back into Kaleidoscope source
+
+use std::collections::HashMap;
+
+use crate::ast::Expr;
+use crate::operator::{Fixity, Operator, OperatorInfo};
+
+/// The side of a binary operator an operand appears on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Left,
+    Right,
+}
+
+/// Returns a value indicating whether or not an operand formed by `child_op`
+/// must be parenthesized when it appears on the given `side` of `parent_op`.
+///
+/// A looser-binding operand always needs parentheses. An operand of equal
+/// precedence only needs them on the side its operator doesn't group towards:
+/// `(a - b) - c` prints as `a - b - c`, but `a - (b - c)` doesn't, and the
+/// other way around for a right-associative `=`.
+/// Operators missing from `prec` bind the loosest, as in the parser.
+pub fn needs_parens(
+    prec: &HashMap<Operator, Fixity>,
+    parent_op: Operator,
+    child_op: Operator,
+    side: Side,
+) -> bool {
+    let parent = OperatorInfo::lookup(prec, parent_op);
+    let child = OperatorInfo::lookup(prec, child_op);
+
+    // Parentheses are needed when the source without them would group the
+    // other way.
+    match side {
+        Side::Left => parent.binds_tighter_than(&child),
+        Side::Right => !child.binds_tighter_than(&parent),
+    }
+}
+
+/// Renders `expr` as source, using only the parentheses required to keep its
+/// structure under the precedence table `prec`.
+pub fn render(expr: &Expr, prec: &HashMap<Operator, Fixity>) -> String {
+    match expr {
+        Expr::Binary { op, lhs, rhs, .. } => format!(
+            "{} {op} {}",
+            render_operand(lhs, *op, Side::Left, prec),
+            render_operand(rhs, *op, Side::Right, prec),
+        ),
+        Expr::Call { name, args, .. } => match expr.as_unary() {
+            // Calls produced by a unary operator render as the operator.
+            Some((op, operand)) => match operand {
+                Expr::Binary { .. }
+                | Expr::For { .. }
+                | Expr::If { .. }
+                | Expr::Seq(..)
+                | Expr::Var { .. } => {
+                    format!("{op}({})", render(operand, prec))
+                },
+                _ => format!("{op}{}", render(operand, prec)),
+            },
+            None => format!("{name}({})", render_list(args, prec)),
+        },
+        Expr::Do(stmts, _) if stmts.is_empty() => "do {}".to_string(),
+        Expr::Do(stmts, _) => format!(
+            "do {{ {} }}",
+            stmts
+                .iter()
+                .map(|stmt| render(stmt, prec))
+                .collect::<Vec<_>>()
+                .join("; ")
+        ),
+        Expr::For {
+            var,
+            start,
+            end,
+            step,
+            body,
+            ..
+        } => {
+            let step = match step {
+                Some(step) => format!(", {}", render(step, prec)),
+                None => String::new(),
+            };
+            format!(
+                "for {var} = {}, {}{step} in {}",
+                render(start, prec),
+                render(end, prec),
+                render(body, prec)
+            )
+        },
+        Expr::If { cond, then, alt, .. } => format!(
+            "if {} then {} else {}",
+            render(cond, prec),
+            render(then, prec),
+            render(alt, prec)
+        ),
+        Expr::NamedArg { name, value, .. } => format!("{name} = {}", render(value, prec)),
+        Expr::Number(value, _) => value.to_string(),
+        Expr::Seq(exprs, _) => exprs
+            .iter()
+            .map(|expr| render_operand(expr, Operator::new(':'), Side::Left, prec))
+            .collect::<Vec<_>>()
+            .join(" : "),
+        Expr::Tuple(elems, _) => format!("({})", render_list(elems, prec)),
+        Expr::Var { bindings, body, .. } => {
+            let bindings = bindings
+                .iter()
+                .map(|(name, init)| match init {
+                    Some(init) => format!("{name} = {}", render(init, prec)),
+                    None => name.clone(),
+                })
+                .collect::<Vec<_>>();
+            format!("var {} in {}", bindings.join(", "), render(body, prec))
+        },
+        Expr::Variable(name, _) => name.clone(),
+    }
+}
+
+fn render_operand(
+    operand: &Expr,
+    parent_op: Operator,
+    side: Side,
+    prec: &HashMap<Operator, Fixity>,
+) -> String {
+    match operand {
+        Expr::Binary { op, .. } if needs_parens(prec, parent_op, *op, side) => {
+            format!("({})", render(operand, prec))
+        },
+        // The `else` branch and the `for` and `var` bodies extend as far right
+        // as possible, and a sequence binds looser than any operator, so these
+        // operands are always parenthesized.
+        Expr::For { .. } | Expr::If { .. } | Expr::Seq(..) | Expr::Var { .. } => {
+            format!("({})", render(operand, prec))
+        },
+        _ => render(operand, prec),
+    }
+}
+
+fn render_list(exprs: &[Expr], prec: &HashMap<Operator, Fixity>) -> String {
+    exprs
+        .iter()
+        .map(|expr| match expr {
+            // An unparenthesized `name = value` argument is a named argument.
+            Expr::Binary { op, .. } if *op == Operator::new('=') => format!("({})", render(expr, prec)),
+            _ => render(expr, prec),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::{DEFAULT_PRECEDENCE, Parser};
+    use crate::testing::parse_expr;
+
+    fn render_source(input: &str) -> String {
+        render(&parse_expr(input), &DEFAULT_PRECEDENCE.iter().copied().collect())
+    }
+
+    #[test]
+    fn parenthesizes_only_when_needed() {
+        assert_eq!(render_source("(a + b) * c"), "(a + b) * c");
+        assert_eq!(render_source("a + (b * c)"), "a + b * c");
+    }
+
+    #[test]
+    fn keeps_assignments_in_arguments_apart_from_named_arguments() {
+        for input in ["f((x = 1), y = 2)", "(a, (b = c))"] {
+            let rendered = render_source(input);
+            assert_eq!(rendered, input);
+            assert_eq!(parse_expr(&rendered), parse_expr(input));
+        }
+    }
+
+    #[test]
+    fn needs_parens_follows_precedence() {
+        let prec = DEFAULT_PRECEDENCE.iter().copied().collect();
+        let (plus, times) = (Operator::new('+'), Operator::new('*'));
+
+        assert!(needs_parens(&prec, times, plus, Side::Left));
+        assert!(!needs_parens(&prec, plus, times, Side::Right));
+    }
+
+    #[test]
+    fn renders_only_operator_calls_as_unary_operators() {
+        assert_eq!(render_source("unaryx(5)"), "unaryx(5)");
+
+        let mut prec = HashMap::from_iter(DEFAULT_PRECEDENCE.iter().copied());
+        let call = Expr::Call {
+            name: "unary!".to_string(),
+            args: vec![Parser::new("a + b", &mut prec).unwrap().parse_expr().unwrap()],
+            span: Default::default(),
+        };
+        assert_eq!(render(&call, &prec), "!(a + b)");
+    }
+}
@@ -0,0 +1,25 @@
+//! Source locations
+
+/// A byte range `start..end` in the source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end:   usize,
+}
+
+impl Span {
+    /// Creates a new `Span` covering `start..end`.
+    pub const fn new(start: usize, end: usize) -> Self { Self { start, end } }
+
+    /// Returns the smallest `Span` covering both `self` and `other`.
+    pub fn join(self, other: Self) -> Self {
+        Self::new(self.start.min(other.start), self.end.max(other.end))
+    }
+}
+
+/// A value along with the source range it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spanned<T> {
+    pub value: T,
+    pub span:  Span,
+}
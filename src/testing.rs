@@ -0,0 +1,219 @@
+//! Helpers for writing tests against the AST
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+use crate::ast::{Expr, Function};
+use crate::operator::{Operator, OperatorInfo};
+use crate::parser::{DEFAULT_PRECEDENCE, Parser};
+use crate::span::Span;
+
+/// Parses `input` as a single expression, with the default operators.
+///
+/// # Panics
+///
+/// If `input` doesn't parse.
+pub fn parse_expr(input: &str) -> Expr {
+    Parser::with_default_precedence(input)
+        .and_then(|mut parser| parser.parse_expr())
+        .unwrap_or_else(|err| panic!("`{input}` doesn't parse: {err}"))
+}
+
+/// Parses every item of `input`, with the default operators.
+///
+/// # Panics
+///
+/// If `input` doesn't parse.
+pub fn parse_items(input: &str) -> Vec<Function> {
+    Parser::with_default_precedence(input)
+        .and_then(|mut parser| parser.parse_all())
+        .unwrap_or_else(|err| panic!("`{input}` doesn't parse: {err}"))
+}
+
+/// A writer whose output stays readable after it's given away, e.g. to an
+/// `Interp`. Clones share their output.
+#[derive(Debug, Clone, Default)]
+pub struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl Buffer {
+    /// Returns everything written so far.
+    ///
+    /// # Panics
+    ///
+    /// If what was written isn't UTF-8.
+    pub fn contents(&self) -> String { String::from_utf8(self.0.borrow().clone()).unwrap() }
+}
+
+impl Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> { self.0.borrow_mut().write(buf) }
+
+    fn flush(&mut self) -> io::Result<()> { Ok(()) }
+}
+
+/// Builds an `Expr` from Kaleidoscope-like syntax, without going through the
+/// lexer or parser:
+///
+/// ```
+/// # use kaleidoscope::ast::Expr;
+/// # use kaleidoscope::expr;
+/// # use kaleidoscope::span::Span;
+/// assert_eq!(expr!(f(x, 2)), Expr::Call {
+///     name: "f".to_string(),
+///     args: vec![
+///         Expr::Variable("x".to_string(), Span::default()),
+///         Expr::Number(2.0, Span::default()),
+///     ],
+///     span: Span::default(),
+/// });
+/// ```
+///
+/// Numbers, variables, calls, parentheses and the default binary operators
+/// are supported. Every node has an empty span, which `==` ignores when
+/// comparing against a parsed tree. Binary operators follow
+/// `DEFAULT_PRECEDENCE` and associate as in the parser, so `expr!(1 + 2 * x)`
+/// is `1 + (2 * x)` and `expr!(a = b = c)` is `a = (b = c)`. Chained `:`
+/// operators make up a single `Seq`.
+#[macro_export]
+macro_rules! expr {
+    // Operand position.
+    (@operand [$($operands:expr),*] [$($ops:expr),*] $name:ident ( $($args:tt)* ) $($rest:tt)*) => {
+        $crate::expr!(@operator [$($operands,)* $crate::ast::Expr::Call {
+            name: stringify!($name).to_string(),
+            args: $crate::expr!(@args [] [] $($args)*),
+            span: $crate::span::Span::default(),
+        }] [$($ops),*] $($rest)*)
+    };
+    (@operand [$($operands:expr),*] [$($ops:expr),*] $name:ident $($rest:tt)*) => {
+        $crate::expr!(@operator [$($operands,)* $crate::ast::Expr::Variable(
+            stringify!($name).to_string(),
+            $crate::span::Span::default(),
+        )] [$($ops),*] $($rest)*)
+    };
+    (@operand [$($operands:expr),*] [$($ops:expr),*] $value:literal $($rest:tt)*) => {
+        $crate::expr!(@operator [$($operands,)* $crate::ast::Expr::Number(
+            $crate::testing::number(stringify!($value)),
+            $crate::span::Span::default(),
+        )] [$($ops),*] $($rest)*)
+    };
+    (@operand [$($operands:expr),*] [$($ops:expr),*] ( $($inner:tt)+ ) $($rest:tt)*) => {
+        $crate::expr!(@operator [$($operands,)* $crate::expr!($($inner)+)] [$($ops),*] $($rest)*)
+    };
+
+    // Operator position.
+    (@operator [$($operands:expr),*] [$($ops:expr),*]) => {
+        $crate::testing::fold_binary(vec![$($operands),*], vec![$($ops),*])
+    };
+    (@operator [$($operands:expr),*] [$($ops:expr),*] $op:tt $($rest:tt)+) => {
+        $crate::expr!(@operand [$($operands),*] [$($ops,)* $crate::testing::operator(stringify!($op))] $($rest)+)
+    };
+
+    // Comma-separated call arguments.
+    (@args [$($done:expr),*] []) => {
+        vec![$($done),*]
+    };
+    (@args [$($done:expr),*] [$($current:tt)+]) => {
+        vec![$($done,)* $crate::expr!($($current)+)]
+    };
+    (@args [$($done:expr),*] [$($current:tt)+] , $($rest:tt)*) => {
+        $crate::expr!(@args [$($done,)* $crate::expr!($($current)+)] [] $($rest)*)
+    };
+    (@args [$($done:expr),*] [$($current:tt)*] $next:tt $($rest:tt)*) => {
+        $crate::expr!(@args [$($done),*] [$($current)* $next] $($rest)*)
+    };
+
+    ($($tokens:tt)+) => {
+        $crate::expr!(@operand [] [] $($tokens)+)
+    };
+}
+
+/// Converts the spelling of a number literal to its value.
+#[doc(hidden)]
+pub fn number(literal: &str) -> f64 {
+    literal
+        .parse()
+        .unwrap_or_else(|_| panic!("`{literal}` is not a number literal"))
+}
+
+/// Converts the spelling of an operator token to the operator.
+#[doc(hidden)]
+pub fn operator(token: &str) -> Operator {
+    match token.chars().collect::<Vec<_>>()[..] {
+        [op] => Operator::new(op),
+        [first, second] => Operator::pair(first, second),
+        _ => panic!("`{token}` is not a binary operator"),
+    }
+}
+
+/// Combines `operands`, separated by `ops`, into a tree of `Binary` nodes by
+/// precedence. There must be exactly one more operand than operators.
+#[doc(hidden)]
+pub fn fold_binary(operands: Vec<Expr>, ops: Vec<Operator>) -> Expr {
+    let prec = HashMap::from_iter(DEFAULT_PRECEDENCE.iter().copied());
+    let info = |op: Operator| {
+        assert!(prec.contains_key(&op), "`{op}` is not a default binary operator");
+        OperatorInfo::lookup(&prec, op)
+    };
+    let reduce = |output: &mut Vec<Expr>, op: Operator| {
+        let rhs = output.pop().expect("missing right operand");
+        let lhs = output.pop().expect("missing left operand");
+        output.push(match (op.as_char(), lhs) {
+            // Chained `:` operators make up a single sequence, as in the parser.
+            (Some(':'), Expr::Seq(mut exprs, span)) => {
+                exprs.push(rhs);
+                Expr::Seq(exprs, span)
+            },
+            (Some(':'), lhs) => Expr::Seq(vec![lhs, rhs], Span::default()),
+            (_, lhs) => Expr::Binary {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+                span: Span::default(),
+            },
+        });
+    };
+
+    let mut operands = operands.into_iter();
+    let mut output = vec![operands.next().expect("missing operand")];
+    let mut pending: Vec<Operator> = vec![];
+
+    for (op, operand) in ops.into_iter().zip(operands) {
+        // Reduce everything that `op` can't take its left operand from.
+        while let Some(&top) = pending.last()
+            && !info(op).binds_tighter_than(&info(top))
+        {
+            pending.pop();
+            reduce(&mut output, top);
+        }
+
+        pending.push(op);
+        output.push(operand);
+    }
+
+    while let Some(top) = pending.pop() {
+        reduce(&mut output, top);
+    }
+
+    output.pop().expect("missing operand")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn builds_the_parsed_tree() {
+        assert_eq!(expr!(1 + 2 * x), parse_expr("1 + 2 * x"));
+        assert_eq!(expr!((1 + 2) * x), parse_expr("(1 + 2) * x"));
+        assert_eq!(expr!(f(x, g(1) - 2)), parse_expr("f(x, g(1) - 2)"));
+        assert_eq!(expr!(a - b - c), parse_expr("a - b - c"));
+    }
+
+    #[test]
+    fn builds_sequences_like_the_parser() {
+        assert_eq!(expr!(a : b), parse_expr("a : b"));
+        assert_eq!(expr!(f(1) : a = 2 : a + b), parse_expr("f(1) : a = 2 : a + b"));
+        assert!(matches!(expr!(a : b : c), Expr::Seq(exprs, _) if exprs.len() == 3));
+    }
+}
@@ -1,17 +1,205 @@
+use core::fmt;
+
+use crate::operator::Operator;
+
 /// The lexer returns tokens [0-255] if it is an unknown character, otherwise
 /// one of these for known things.
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
-    Binary,
     Comma,
-    Comment,
-    Def,
+    Comment(String),
     EOF,
-    Extern,
     Ident(String),
+    Keyword(Keyword),
+    LBrace,
     LParen,
     Number(f64),
     Op(char),
+    /// A two-character operator, such as `<=`.
+    Op2([char; 2]),
+    RBrace,
     RParen,
-    // ! remeber to update `Lexer:lex_ident`
+    Str(String),
+}
+
+impl Token {
+    /// Returns a short description of the kind of this token, regardless of
+    /// its value.
+    pub const fn kind(&self) -> &'static str {
+        match self {
+            Self::Comma => "comma",
+            Self::Comment(_) => "comment",
+            Self::EOF => "end of input",
+            Self::Ident(_) => "identifier",
+            Self::Keyword(_) => "keyword",
+            Self::LBrace | Self::RBrace => "brace",
+            Self::LParen | Self::RParen => "parenthesis",
+            Self::Number(_) => "number",
+            Self::Op(_) | Self::Op2(_) => "operator",
+            Self::Str(_) => "string",
+        }
+    }
+
+    /// Returns a value indicating whether or not this is an `Op` or `Op2`
+    /// token.
+    pub const fn is_operator(&self) -> bool { matches!(self, Self::Op(_) | Self::Op2(_)) }
+
+    /// Returns a value indicating whether or not this is a reserved word.
+    pub const fn is_keyword(&self) -> bool { matches!(self, Self::Keyword(_)) }
+
+    /// Returns a value indicating whether or not this token is punctuation
+    /// that separates or groups other tokens: a comma, a parenthesis or a
+    /// brace.
+    pub const fn is_delimiter(&self) -> bool {
+        matches!(
+            self,
+            Self::Comma | Self::LBrace | Self::LParen | Self::RBrace | Self::RParen
+        )
+    }
+}
+
+impl fmt::Display for Token {
+    /// Formats the token as it could appear in the source. `EOF` has no
+    /// spelling, so it renders as `<eof>`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Comma => f.write_str(","),
+            Self::Comment(text) => write!(f, "#{text}"),
+            Self::EOF => f.write_str("<eof>"),
+            Self::Ident(name) => f.write_str(name),
+            Self::Keyword(keyword) => f.write_str(keyword.as_str()),
+            Self::LBrace => f.write_str("{"),
+            Self::LParen => f.write_str("("),
+            Self::Number(value) => write!(f, "{value}"),
+            Self::Op(op) => write!(f, "{op}"),
+            Self::Op2([first, second]) => write!(f, "{first}{second}"),
+            Self::RBrace => f.write_str("}"),
+            Self::RParen => f.write_str(")"),
+            Self::Str(text) => write!(f, "{text:?}"),
+        }
+    }
+}
+
+impl Token {
+    /// Returns the operator spelled by an `Op` or `Op2` token.
+    pub const fn as_operator(&self) -> Option<Operator> {
+        match *self {
+            Self::Op(op) => Some(Operator::new(op)),
+            Self::Op2([first, second]) => Some(Operator::pair(first, second)),
+            _ => None,
+        }
+    }
+}
+
+/// The reserved words of the language.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Keyword {
+    Binary,
+    Def,
+    Do,
+    Else,
+    Extern,
+    For,
+    If,
+    In,
+    Then,
+    Unary,
+    Var,
+    // ! remember to update `KEYWORDS`
+}
+
+/// Maps the spelling of each keyword to its `Keyword`.
+pub const KEYWORDS: &[(&str, Keyword)] = &[
+    ("binary", Keyword::Binary),
+    ("def", Keyword::Def),
+    ("do", Keyword::Do),
+    ("else", Keyword::Else),
+    ("extern", Keyword::Extern),
+    ("for", Keyword::For),
+    ("if", Keyword::If),
+    ("in", Keyword::In),
+    ("then", Keyword::Then),
+    ("unary", Keyword::Unary),
+    ("var", Keyword::Var),
+];
+
+impl Keyword {
+    /// Returns the keyword spelled `ident`, if any.
+    pub fn from_ident(ident: &str) -> Option<Self> {
+        KEYWORDS
+            .iter()
+            .find(|(name, _)| *name == ident)
+            .map(|&(_, keyword)| keyword)
+    }
+
+    /// Returns the spelling of the keyword.
+    pub fn as_str(self) -> &'static str {
+        KEYWORDS
+            .iter()
+            .find(|&&(_, keyword)| keyword == self)
+            .map(|(name, _)| *name)
+            .expect("every keyword is listed in KEYWORDS")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+
+    #[test]
+    fn keywords_round_trip() {
+        for &(name, keyword) in KEYWORDS {
+            assert_eq!(Keyword::from_ident(name), Some(keyword));
+            assert_eq!(keyword.as_str(), name);
+            assert_eq!(tokenize(name).unwrap(), [Token::Keyword(keyword)]);
+        }
+        assert_eq!(Keyword::from_ident("define"), None);
+    }
+
+    #[test]
+    fn displays_tokens_as_source() {
+        for (token, expected) in [
+            (Token::Comma, ","),
+            (Token::Comment(" note".to_string()), "# note"),
+            (Token::EOF, "<eof>"),
+            (Token::Ident("x".to_string()), "x"),
+            (Token::Keyword(Keyword::Def), "def"),
+            (Token::LBrace, "{"),
+            (Token::LParen, "("),
+            (Token::Number(3.0), "3"),
+            (Token::Number(2.5), "2.5"),
+            (Token::Op('+'), "+"),
+            (Token::Op2(['<', '=']), "<="),
+            (Token::RBrace, "}"),
+            (Token::RParen, ")"),
+            (Token::Str("hi".to_string()), "\"hi\""),
+        ] {
+            assert_eq!(token.to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn classifies_every_token() {
+        for (token, operator, keyword, delimiter) in [
+            (Token::Comma, false, false, true),
+            (Token::Comment(String::new()), false, false, false),
+            (Token::EOF, false, false, false),
+            (Token::Ident("x".to_string()), false, false, false),
+            (Token::Keyword(Keyword::Def), false, true, false),
+            (Token::LBrace, false, false, true),
+            (Token::LParen, false, false, true),
+            (Token::Number(1.0), false, false, false),
+            (Token::Op('+'), true, false, false),
+            (Token::Op2(['=', '=']), true, false, false),
+            (Token::RBrace, false, false, true),
+            (Token::RParen, false, false, true),
+            (Token::Str(String::new()), false, false, false),
+        ] {
+            assert_eq!(token.is_operator(), operator, "{token:?}");
+            assert_eq!(token.is_keyword(), keyword, "{token:?}");
+            assert_eq!(token.is_delimiter(), delimiter, "{token:?}");
+            assert_eq!(token.as_operator().is_some(), operator, "{token:?}");
+        }
+    }
 }
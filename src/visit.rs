@@ -0,0 +1,169 @@
+//! Traversal of the AST
+
+use std::collections::BTreeSet;
+
+use crate::ast::Expr;
+use crate::operator::Operator;
+
+/// Visits the nodes of an expression tree. Each method defaults to visiting
+/// the children of its node, so implementations only override the nodes they
+/// care about.
+pub trait Visitor {
+    /// Visits any expression, by dispatching on its kind with `walk_expr`.
+    fn visit_expr(&mut self, expr: &Expr) { walk_expr(self, expr) }
+
+    fn visit_binary(&mut self, _op: Operator, lhs: &Expr, rhs: &Expr) {
+        self.visit_expr(lhs);
+        self.visit_expr(rhs);
+    }
+
+    fn visit_call(&mut self, _name: &str, args: &[Expr]) {
+        args.iter().for_each(|arg| self.visit_expr(arg))
+    }
+
+    fn visit_do(&mut self, stmts: &[Expr]) { stmts.iter().for_each(|stmt| self.visit_expr(stmt)) }
+
+    fn visit_for(&mut self, _var: &str, start: &Expr, end: &Expr, step: Option<&Expr>, body: &Expr) {
+        self.visit_expr(start);
+        self.visit_expr(end);
+        if let Some(step) = step {
+            self.visit_expr(step);
+        }
+        self.visit_expr(body);
+    }
+
+    fn visit_if(&mut self, cond: &Expr, then: &Expr, alt: &Expr) {
+        self.visit_expr(cond);
+        self.visit_expr(then);
+        self.visit_expr(alt);
+    }
+
+    fn visit_named_arg(&mut self, _name: &str, value: &Expr) { self.visit_expr(value) }
+
+    fn visit_number(&mut self, _value: f64) {}
+
+    fn visit_seq(&mut self, exprs: &[Expr]) { exprs.iter().for_each(|expr| self.visit_expr(expr)) }
+
+    fn visit_tuple(&mut self, elems: &[Expr]) { elems.iter().for_each(|elem| self.visit_expr(elem)) }
+
+    fn visit_var(&mut self, bindings: &[(String, Option<Expr>)], body: &Expr) {
+        for init in bindings.iter().filter_map(|(_, init)| init.as_ref()) {
+            self.visit_expr(init);
+        }
+        self.visit_expr(body);
+    }
+
+    fn visit_variable(&mut self, _name: &str) {}
+}
+
+/// Calls the method of `visitor` matching the kind of `expr`.
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Binary { op, lhs, rhs, .. } => visitor.visit_binary(*op, lhs, rhs),
+        Expr::Call { name, args, .. } => visitor.visit_call(name, args),
+        Expr::Do(stmts, _) => visitor.visit_do(stmts),
+        Expr::For {
+            var,
+            start,
+            end,
+            step,
+            body,
+            ..
+        } => visitor.visit_for(var, start, end, step.as_deref(), body),
+        Expr::If { cond, then, alt, .. } => visitor.visit_if(cond, then, alt),
+        Expr::NamedArg { name, value, .. } => visitor.visit_named_arg(name, value),
+        Expr::Number(value, _) => visitor.visit_number(*value),
+        Expr::Seq(exprs, _) => visitor.visit_seq(exprs),
+        Expr::Tuple(elems, _) => visitor.visit_tuple(elems),
+        Expr::Var { bindings, body, .. } => visitor.visit_var(bindings, body),
+        Expr::Variable(name, _) => visitor.visit_variable(name),
+    }
+}
+
+/// Collects the variables an expression refers to without binding them
+/// itself, i.e. those not introduced by an enclosing `for` or `var`.
+#[derive(Debug, Default)]
+pub struct FreeVars {
+    /// The variables bound at the current node, innermost last.
+    bound:    Vec<String>,
+    /// The free variables found so far.
+    pub vars: BTreeSet<String>,
+}
+
+impl FreeVars {
+    /// Returns the free variables of `expr`.
+    pub fn of(expr: &Expr) -> BTreeSet<String> {
+        let mut free_vars = Self::default();
+        free_vars.visit_expr(expr);
+        free_vars.vars
+    }
+}
+
+impl Visitor for FreeVars {
+    fn visit_for(&mut self, var: &str, start: &Expr, end: &Expr, step: Option<&Expr>, body: &Expr) {
+        // The loop variable is not in scope in `start`.
+        self.visit_expr(start);
+
+        self.bound.push(var.to_string());
+        self.visit_expr(end);
+        if let Some(step) = step {
+            self.visit_expr(step);
+        }
+        self.visit_expr(body);
+        self.bound.pop();
+    }
+
+    fn visit_var(&mut self, bindings: &[(String, Option<Expr>)], body: &Expr) {
+        // Each initializer sees the bindings before it.
+        for (name, init) in bindings {
+            if let Some(init) = init {
+                self.visit_expr(init);
+            }
+            self.bound.push(name.clone());
+        }
+
+        self.visit_expr(body);
+        self.bound.truncate(self.bound.len() - bindings.len());
+    }
+
+    fn visit_variable(&mut self, name: &str) {
+        if !self.bound.iter().any(|bound| bound == name) {
+            self.vars.insert(name.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::parse_expr;
+
+    fn free_vars(input: &str) -> Vec<String> { FreeVars::of(&parse_expr(input)).into_iter().collect() }
+
+    #[test]
+    fn collects_free_variables() {
+        assert_eq!(free_vars("a + f(b, c)"), ["a", "b", "c"]);
+        assert_eq!(free_vars("f(1)"), [] as [&str; 0]);
+    }
+
+    #[test]
+    fn skips_variables_bound_by_for_and_var() {
+        assert_eq!(free_vars("for i = i, i < n in f(i)"), ["i", "n"]);
+        assert_eq!(free_vars("var a = a, b = a in a + b + c"), ["a", "c"]);
+    }
+
+    #[test]
+    fn default_methods_visit_every_node() {
+        #[derive(Default)]
+        struct Numbers(Vec<f64>);
+
+        impl Visitor for Numbers {
+            fn visit_number(&mut self, value: f64) { self.0.push(value) }
+        }
+
+        let expr = parse_expr("if 1 then do { 2; f(3) } else for i = 4, 5 in (6, 7)");
+        let mut numbers = Numbers::default();
+        numbers.visit_expr(&expr);
+        assert_eq!(numbers.0, [1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0]);
+    }
+}
@@ -0,0 +1,138 @@
+//! Runs the `kaleidoscope` binary the way a shell would.
+
+use std::io::Write as _;
+use std::path::PathBuf;
+use std::process::{Command, Output, Stdio};
+
+/// Writes `source` to a file named after `name` in the temporary directory,
+/// returning its path.
+fn source_file(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("kaleidoscope-cli-{}-{name}.k", std::process::id()));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+/// Runs the binary on the file `path`, with the interpreter.
+fn run_file(path: &PathBuf) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_kaleidoscope"))
+        .arg("--interp")
+        .arg("--file")
+        .arg(path)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    std::fs::remove_file(path).unwrap();
+    output
+}
+
+#[test]
+fn exits_with_the_last_value_of_a_file() {
+    let output = run_file(&source_file("exit", "def f(x) x + 1;\nf(0);\n2"));
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "=> 1\n=> 2\n");
+}
+
+#[test]
+fn fails_when_the_last_value_is_negative() {
+    let output = run_file(&source_file("negative", "1;\n-1"));
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "=> 1\n=> -1\n");
+}
+
+#[test]
+fn skips_the_shebang_of_a_script() {
+    let source = "#!/usr/bin/env kaleidoscope\n# A comment.\ndef sq(x) x*x;\nsq(2)\n";
+    let output = run_file(&source_file("shebang", source));
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "=> 4\n");
+}
+
+#[test]
+fn runs_files_without_the_prompt() {
+    let output = run_file(&source_file("file", "def sq(x) x*x;\nsq(3);\n0\n"));
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "=> 9\n=> 0\n");
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("Parsed a function definition: sq")
+    );
+
+    let output = Command::new(env!("CARGO_BIN_EXE_kaleidoscope"))
+        .args(["--file", "does-not-exist.k"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        String::from_utf8(output.stderr)
+            .unwrap()
+            .starts_with("Error reading does-not-exist.k")
+    );
+}
+
+#[test]
+fn prints_what_the_debug_flags_ask_for() {
+    let output = |flags: &[&str]| {
+        let output = Command::new(env!("CARGO_BIN_EXE_kaleidoscope"))
+            .args(["--interp", "-e", "1 + 2"])
+            .args(flags)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        String::from_utf8(output.stdout).unwrap()
+    };
+
+    assert_eq!(output(&[]), "=> 3\n");
+    assert!(output(&["--dl"]).starts_with("-> Attempting to parse lexed input: \n[Number(1.0), Op('+')"));
+    assert!(output(&["--dp"]).starts_with("-> Expression parsed: \n"));
+    assert!(output(&["--ast"]).starts_with("-> Expression tree: \n"));
+
+    let both = output(&["--dl", "--dp"]);
+    let tokens = both.find("-> Attempting to parse lexed input").unwrap();
+    assert!(both[tokens..].contains("-> Expression parsed"), "{both}");
+    assert!(both.ends_with("=> 3\n"), "{both}");
+}
+
+#[test]
+fn fails_when_the_evaluated_input_does() {
+    let eval = |input: &str| {
+        Command::new(env!("CARGO_BIN_EXE_kaleidoscope"))
+            .args(["--interp", "-e", input])
+            .stdin(Stdio::null())
+            .output()
+            .unwrap()
+    };
+
+    let output = eval("1\n2");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(String::from_utf8(output.stdout).unwrap(), "=> 1\n=> 2\n");
+
+    for input in ["1 +", "def f(x) x; f(1, 2)", "y"] {
+        let output = eval(input);
+        assert_eq!(output.status.code(), Some(1), "{input}: {output:?}");
+    }
+}
+
+#[test]
+fn prints_the_values_of_interpreted_expressions() {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kaleidoscope"))
+        .arg("--interp")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(b"def sq(x) x*x\nsq(5)\nquit\n")
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "\n?> \n?> => 25\n\n?> "
+    );
+}